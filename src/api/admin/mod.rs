@@ -0,0 +1,160 @@
+use axum::extract::Path;
+use axum::{
+    extract::State,
+    routing::{get, post, put},
+    Json, Router,
+};
+
+use crate::events::Event;
+use crate::rpc::models::ChainRpcStats;
+use crate::trades::models::{ResolveTradeRequest, Trade};
+use crate::withdrawals::models::{Withdrawal, WithdrawalLimits};
+use crate::{events, trades, withdrawals, AppState};
+
+use super::auth::models::Claims;
+use super::AppError;
+
+pub fn router(app_state: &AppState) -> Router {
+    Router::new()
+        .route("/withdrawals", get(get_pending_withdrawals))
+        .route("/withdrawals/{id}/approve", post(approve_withdrawal))
+        .route("/withdrawals/{id}/reject", post(reject_withdrawal))
+        .route("/users/{id}/withdrawal-limits", get(get_withdrawal_limits))
+        .route("/users/{id}/withdrawal-limits", put(set_withdrawal_limits))
+        .route("/transactions", get(get_disputed_transactions))
+        .route("/transactions/{id}/resolve", post(resolve_transaction))
+        .route("/rpc", get(get_rpc_stats))
+        .route("/events", get(get_events))
+        .with_state(app_state.clone())
+}
+
+pub async fn get_pending_withdrawals(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<Withdrawal>>, AppError> {
+    claims.require_admin()?;
+    println!("Getting withdrawals pending approval");
+
+    let withdrawals = withdrawals::pending_approval(&state.database).await?;
+
+    Ok(Json(withdrawals))
+}
+
+pub async fn approve_withdrawal(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Withdrawal>, AppError> {
+    claims.require_admin()?;
+    println!("Approving withdrawal {}", id);
+
+    let withdrawal = withdrawals::review(&state.database, &id, &claims.sub, true).await?;
+
+    Ok(Json(withdrawal))
+}
+
+/// Rejected withdrawals are marked failed, so the worker refunds them.
+pub async fn reject_withdrawal(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Withdrawal>, AppError> {
+    claims.require_admin()?;
+    println!("Rejecting withdrawal {}", id);
+
+    let withdrawal = withdrawals::review(&state.database, &id, &claims.sub, false).await?;
+
+    Ok(Json(withdrawal))
+}
+
+/// The limits that apply to a user, their own or the defaults.
+pub async fn get_withdrawal_limits(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<WithdrawalLimits>, AppError> {
+    claims.require_admin()?;
+    println!("Getting withdrawal limits of {}", id);
+
+    let limits = withdrawals::limits_for(&state.database, &state.withdrawal_limits, &id).await?;
+
+    Ok(Json(limits))
+}
+
+/// Sets a user's own limits. Any left out fall back to the defaults.
+pub async fn set_withdrawal_limits(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+    Json(payload): Json<WithdrawalLimits>,
+) -> Result<Json<WithdrawalLimits>, AppError> {
+    claims.require_admin()?;
+    println!("Setting withdrawal limits of {}: {:?}", id, payload);
+
+    withdrawals::set_limits(&state.database, &id, &payload, &claims.sub).await?;
+    let limits = withdrawals::limits_for(&state.database, &state.withdrawal_limits, &id).await?;
+
+    Ok(Json(limits))
+}
+
+pub async fn get_disputed_transactions(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<Trade>>, AppError> {
+    claims.require_admin()?;
+    println!("Getting disputed transactions");
+
+    let trades = trades::disputed(&state.database).await?;
+
+    Ok(Json(trades))
+}
+
+/// Either releases a disputed trade's crypto to the taker or cancels it.
+pub async fn resolve_transaction(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+    Json(payload): Json<ResolveTradeRequest>,
+) -> Result<Json<Trade>, AppError> {
+    claims.require_admin()?;
+    println!("Resolving transaction {}: {:?}", id, payload);
+
+    let trade = trades::resolve(&state.database, &id, &claims.sub, payload.release).await?;
+
+    Ok(Json(trade))
+}
+
+/// Health, latency and error rates of every chain's RPC endpoints.
+pub async fn get_rpc_stats(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<ChainRpcStats>>, AppError> {
+    claims.require_admin()?;
+    println!("Getting RPC stats");
+
+    let stats = state
+        .chains
+        .all()
+        .iter()
+        .map(|chain| ChainRpcStats {
+            chain_id: chain.id(),
+            endpoints: chain.rpc.stats(),
+        })
+        .collect();
+
+    Ok(Json(stats))
+}
+
+/// The latest trades expired, offers closed and nonces purged by the expiry
+/// job.
+pub async fn get_events(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<Event>>, AppError> {
+    claims.require_admin()?;
+    println!("Getting events");
+
+    let events = events::latest(&state.database, 100).await?;
+
+    Ok(Json(events))
+}
@@ -1,3 +1,4 @@
+use alloy::primitives::Address;
 use axum::extract::State;
 use axum::http::HeaderValue;
 use axum::routing::{get, post};
@@ -6,15 +7,18 @@ use hyper::HeaderMap;
 use jsonwebtoken::TokenData;
 use jsonwebtoken::{decode, encode, Header, Validation};
 use models::{
-    AuthBody, AuthError, Claims, GenerateNonceResponse, GetNonceResult, Keys,
+    AuthBody, AuthError, Claims, GenerateNonceResponse, GetNonceResult, Keys, Role,
     VerifySiweAndCreateUserRequest,
 };
 use siwe::{generate_nonce, Message};
 use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
+use surrealdb::engine::any::Any;
 use surrealdb::sql::Thing;
+use surrealdb::Surreal;
 
-use crate::AppState;
+use crate::events::EventKind;
+use crate::{db, wallets, AppState};
 
 use super::AppError;
 
@@ -42,7 +46,6 @@ pub async fn save_nonce(value: String, state: State<AppState>) -> Result<(), App
     println!("Saving nonce");
     let _response = state.database
       .query("
-        DELETE nonce WHERE exp < time::now() RETURN BEFORE;
         CREATE ONLY nonce SET value = type::string($value), exp = time::now() + 5m, iat = time::now();
       ")
         .bind(("value", value))
@@ -59,45 +62,20 @@ pub async fn verify_siwe_and_create_user(
 ) -> Result<(HeaderMap, Json<AuthBody>), AppError> {
     println!("payload: {}", payload.message);
 
-    let siwe_message = Message::from_str(&payload.message).unwrap();
-
-    println!("payload: {}", siwe_message);
-    let signature: [u8; 65] =
-        prefix_hex::decode(&payload.signature).expect("Failed to decode signature");
-
-    let nonce_exists = state
-        .database
-        .query(
-            "
-            DELETE nonce WHERE exp < time::now() RETURN BEFORE;
-            SELECT id, value FROM nonce WHERE value == type::string($value);
-        ",
-        )
-        .bind(("value", siwe_message.nonce.clone()))
-        .await?
-        .take::<Option<GetNonceResult>>(1)?
-        .is_some();
-
-    if !nonce_exists {
-        return Err(AppError(anyhow::anyhow!("Invalid nonce")));
+    let siwe_message = verify_siwe(&state, &payload.message, &payload.signature).await?;
+    // Only the address that signed can be signed up or signed in as, and
+    // both the account and its role come from it.
+    let signer = Address::from(siwe_message.address);
+    if !payload.address.eq_ignore_ascii_case(&signer.to_string()) {
+        return Err(AuthError::AddressMismatch(payload.address).into());
     }
 
-    siwe_message
-        .verify(&signature, &siwe::VerificationOpts::default())
-        .await
-        .unwrap();
-
-    println!("SIWE message verified");
-
-    let user_id = create_user(
-        State(state.clone()),
-        &payload.address.clone().to_lowercase(),
-    )
-    .await?;
+    let role = role_of(&state, signer);
+    let user_id = create_user(State(state.clone()), &signer.to_string().to_lowercase()).await?;
 
     println!("User created");
 
-    let token_str = generate_jwt(user_id.to_string(), State(state.clone())).await?;
+    let token_str = generate_jwt(user_id.to_string(), role, State(state.clone())).await?;
 
     println!("Token generated");
 
@@ -117,7 +95,61 @@ pub async fn verify_siwe_and_create_user(
     ))
 }
 
+/// Admin for the configured admin addresses, a plain user otherwise.
+fn role_of(state: &AppState, signer: Address) -> Role {
+    if state.admin_addresses.contains(&signer) {
+        Role::Admin
+    } else {
+        Role::User
+    }
+}
+
+/// Checks that a SIWE `message` is for one of our chains and was signed by the
+/// address it names, then uses up the nonce it carries so the message cannot
+/// sign anyone in again.
+pub async fn verify_siwe(
+    state: &AppState,
+    message: &str,
+    signature: &str,
+) -> Result<Message, AppError> {
+    let siwe_message = Message::from_str(message)?;
+    state.chains.get(siwe_message.chain_id)?;
+
+    println!("payload: {}", siwe_message);
+    let signature: [u8; 65] = prefix_hex::decode(signature)
+        .map_err(|_| AppError(anyhow::anyhow!("Failed to decode signature")))?;
+
+    siwe_message
+        .verify(&signature, &siwe::VerificationOpts::default())
+        .await?;
+
+    if !consume_nonce(&state.database, &siwe_message.nonce).await? {
+        return Err(AppError(anyhow::anyhow!("Invalid nonce")));
+    }
+
+    println!("SIWE message verified");
+
+    Ok(siwe_message)
+}
+
+/// Deletes the nonce if we issued it and it has not expired, returning
+/// whether it did. Of two requests racing with the same nonce only one gets
+/// it.
+async fn consume_nonce(db: &Surreal<Any>, value: &str) -> Result<bool, surrealdb::Error> {
+    let consumed = db
+        .query(
+            "DELETE nonce WHERE value = type::string($value) AND exp >= time::now() RETURN BEFORE",
+        )
+        .bind(("value", value.to_string()))
+        .await?
+        .take::<Vec<GetNonceResult>>(0)?;
+
+    Ok(!consumed.is_empty())
+}
+
 // USER
+/// Finds the user `address` is linked to, or signs them up with it. The
+/// address must be the one that signed in.
 pub async fn create_user(
     State(state): State<AppState>,
     address: &String,
@@ -126,14 +158,7 @@ pub async fn create_user(
 
     println!("User address: {}", address);
 
-    let user_id = match db
-        .query(
-            "SELECT VALUE id FROM user WHERE address = type::string(string::lowercase($address))",
-        )
-        .bind(("address", address.clone()))
-        .await?
-        .take::<Option<Thing>>(0)?
-    {
+    let user_id = match wallets::owner(&db, address).await? {
         Some(existing_user) => {
             println!("User found, returning user id");
             existing_user.to_string()
@@ -141,12 +166,14 @@ pub async fn create_user(
         None => {
             println!("User not found, creating user");
             let user_id = db
-                .query("CREATE ONLY user SET address = type::string(string::lowercase($address)), balance = type::number(0) RETURN VALUE id")
+                .query("CREATE ONLY user SET address = type::string(string::lowercase($address)) RETURN VALUE id")
                 .bind(("address", address.clone()))
                 .await?
                 .take::<Option<Thing>>(0)?
                 .ok_or(AppError(anyhow::anyhow!("Failed to create a user")))?;
 
+            wallets::link(&db, &user_id.to_string(), address).await?;
+
             user_id.to_string()
         }
     };
@@ -156,7 +183,11 @@ pub async fn create_user(
 }
 
 // JWT
-pub async fn generate_jwt(id: String, app_state: State<AppState>) -> Result<String, AuthError> {
+pub async fn generate_jwt(
+    id: String,
+    role: Role,
+    app_state: State<AppState>,
+) -> Result<String, AuthError> {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards")
@@ -165,6 +196,7 @@ pub async fn generate_jwt(id: String, app_state: State<AppState>) -> Result<Stri
     let user_claims = Claims {
         sub: id,
         exp: (now + 3600) as usize,
+        role,
     };
 
     let secret = app_state.jwt_secret.clone();
@@ -184,3 +216,58 @@ async fn verify_jwt(token: String, app_state: &AppState) -> Result<TokenData<Cla
 
     Ok(token_data)
 }
+
+/// Deletes the SIWE nonces past their expiry, which can no longer sign anyone
+/// in, and returns how many there were. Each gets an event.
+pub async fn purge_expired_nonces(db: &Surreal<Any>) -> Result<usize, surrealdb::Error> {
+    let mut response = db
+        .query(
+            "
+            BEGIN TRANSACTION;
+            LET $purged = (DELETE nonce WHERE exp < time::now() RETURN BEFORE);
+            FOR $nonce IN $purged {
+                CREATE events SET kind = type::string($eventKind), subject = $nonce.id, createdAt = time::now();
+            };
+            RETURN $purged;
+            COMMIT TRANSACTION;
+            ",
+        )
+        .bind(("eventKind", EventKind::NoncePurged))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        return Err(error);
+    }
+    let purged = response.take::<Vec<GetNonceResult>>(0)?;
+
+    Ok(purged.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn a_nonce_signs_in_once() {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        db.query(
+            "
+            CREATE nonce SET value = 'fresh', exp = time::now() + 5m, iat = time::now();
+            CREATE nonce SET value = 'stale', exp = time::now() - 1m, iat = time::now() - 6m;
+            ",
+        )
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+
+        assert!(consume_nonce(&db, "fresh").await.unwrap());
+        assert!(!consume_nonce(&db, "fresh").await.unwrap());
+        assert!(!consume_nonce(&db, "stale").await.unwrap());
+        assert!(!consume_nonce(&db, "unknown").await.unwrap());
+    }
+}
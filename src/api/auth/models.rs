@@ -9,6 +9,7 @@ use jsonwebtoken::{DecodingKey, EncodingKey};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use surrealdb::sql::Thing;
+use thiserror::Error;
 
 use crate::AppState;
 
@@ -46,32 +47,78 @@ impl Keys {
     }
 }
 
+/// What a user is allowed to do beyond managing their own account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    /// Signed in with one of the configured admin addresses.
+    Admin,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub exp: usize,
     pub sub: String,
+    #[serde(default)]
+    pub role: Role,
 }
 
-#[derive(Debug)]
+impl Claims {
+    pub fn require_admin(&self) -> Result<(), AuthError> {
+        match self.role {
+            Role::Admin => Ok(()),
+            Role::User => Err(AuthError::Forbidden),
+        }
+    }
+
+    /// Passes if the claims belong to one of the users a record belongs to,
+    /// or to an admin.
+    pub fn require_owner(&self, owners: &[&Thing]) -> Result<(), AuthError> {
+        if self.role == Role::Admin || owners.iter().any(|owner| owner.to_string() == self.sub) {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden)
+        }
+    }
+}
+
+#[derive(Debug, Error)]
 pub enum AuthError {
+    #[error("Wrong credentials")]
     WrongCredentials,
+    #[error("Missing credentials")]
     MissingCredentials,
+    #[error("Token creation error")]
     TokenCreation,
+    #[error("Invalid token")]
     InvalidToken,
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Address {0} did not sign the message")]
+    AddressMismatch(String),
+}
+
+impl AuthError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::WrongCredentials => StatusCode::UNAUTHORIZED,
+            AuthError::MissingCredentials
+            | AuthError::InvalidToken
+            | AuthError::AddressMismatch(_) => StatusCode::BAD_REQUEST,
+            AuthError::TokenCreation => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthError::Forbidden => StatusCode::FORBIDDEN,
+        }
+    }
 }
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Wrong credentials"),
-            AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
-            AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
-            AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
-        };
         let body = Json(json!({
-            "error": error_message,
+            "error": self.to_string(),
         }));
-        (status, body).into_response()
+        (self.status_code(), body).into_response()
     }
 }
 
@@ -89,7 +136,7 @@ impl FromRequestParts<AppState> for Claims {
 
         let token = cookies
             .get("token")
-            .ok_or_else(|| AuthError::MissingCredentials)?
+            .ok_or(AuthError::MissingCredentials)?
             .to_string();
 
         let token_data = verify_jwt(token, state)
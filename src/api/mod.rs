@@ -1,18 +1,64 @@
+pub mod admin;
 pub mod auth;
 pub mod private;
 pub mod public;
 use crate::api::auth::models::AuthError;
+use crate::chain::models::ChainError;
+use crate::deposits::models::DepositError;
+use crate::ledger::models::LedgerError;
+use crate::money::models::MoneyError;
+use crate::offers::models::OfferError;
+use crate::tokens::models::TokenError;
+use crate::trades::models::TradeError;
+use crate::wallets::models::WalletError;
+use crate::withdrawals::models::WithdrawalError;
 use axum::response::{IntoResponse, Response};
 use hyper::StatusCode;
 pub struct AppError(anyhow::Error);
 
+impl AppError {
+    fn status_code(&self) -> StatusCode {
+        if let Some(error) = self.0.downcast_ref::<AuthError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<MoneyError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<TokenError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<ChainError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<DepositError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<LedgerError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<OfferError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<TradeError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<WalletError>() {
+            return error.status_code();
+        }
+        if let Some(error) = self.0.downcast_ref::<WithdrawalError>() {
+            return error.status_code();
+        }
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        let status = self.status_code();
+        if status.is_server_error() {
+            return (status, format!("Something went wrong: {}", self.0)).into_response();
+        }
+        (status, self.0.to_string()).into_response()
     }
 }
 
@@ -24,9 +70,3 @@ where
         Self(err.into())
     }
 }
-
-impl From<AuthError> for AppError {
-    fn from(error: AuthError) -> Self {
-        AppError(anyhow::anyhow!("{:?}", error))
-    }
-}
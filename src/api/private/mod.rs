@@ -1,14 +1,14 @@
-use crate::api::public::models::Offer;
-use crate::AppState;
-use alloy::hex::FromHex;
-use alloy::network::TransactionBuilder;
-use alloy::primitives::{Address, U256};
-use alloy::providers::Provider;
-use alloy::rpc::types::TransactionRequest;
-use alloy::signers::k256::ecdsa::SigningKey;
-use alloy::signers::local::PrivateKeySigner;
-use alloy::{primitives::FixedBytes, providers::ProviderBuilder, sol};
-use axum::extract::Path;
+use crate::api::public::models::{DepositAddressResponse, Offer};
+use crate::deposits::models::{Deposit, DepositError};
+use crate::ledger::models::LedgerEntry;
+use crate::money::models::Amount;
+use crate::trades::models::Trade;
+use crate::wallets::models::Wallet;
+use crate::withdrawals::models::{Withdrawal, WithdrawalAddress, WithdrawalError, WithdrawalQuote};
+use crate::{deposits, ledger, offers, trades, wallets, withdrawals, AppState};
+use alloy::primitives::{Address, TxHash};
+use alloy::sol;
+use axum::extract::{Path, Query};
 use axum::routing::delete;
 use axum::{
     extract::State,
@@ -16,15 +16,17 @@ use axum::{
     Json, Router,
 };
 use hyper::StatusCode;
-use std::ops::Div;
 
 use models::{
-    ConfirmDepositRequest, CreateOfferRequest, CreateTransactionRequest, GetAggregatedFeeRequest,
-    GetAggregatedFeeResponse, GetBalanceResponse, WithdrawRequest,
+    AllowWithdrawalAddressRequest, ConfirmDepositRequest, CreateOfferRequest,
+    CreateTransactionRequest, GetAggregatedFeeRequest, GetAggregatedFeeResponse, GetBalanceQuery,
+    GetBalanceResponse, GetDepositQuery, LinkWalletRequest, RemoveWithdrawalAddressRequest,
+    WithdrawQuoteRequest, WithdrawRequest,
 };
-use std::{str::FromStr, time::Duration};
+use std::str::FromStr;
 
-use super::{auth::models::Claims, AppError};
+use super::auth::{models::Claims, verify_siwe};
+use super::AppError;
 
 pub mod models;
 
@@ -33,12 +35,33 @@ pub fn router(app_state: &AppState) -> Router {
         .route("/", get(root))
         .route("/offers", post(create_offer))
         .route("/transactions", post(create_transaction))
+        .route("/transactions", get(get_transactions))
+        .route("/transactions/{id}", get(get_transaction))
+        .route("/transactions/{id}/payment-sent", post(mark_payment_sent))
+        .route("/transactions/{id}/confirm-payment", post(confirm_payment))
+        .route("/transactions/{id}/cancel", post(cancel_transaction))
+        .route("/transactions/{id}/dispute", post(dispute_transaction))
         .route("/deposit", post(confirm_deposit))
+        .route("/deposits", get(get_deposits))
+        .route("/deposits/{txHash}", get(get_deposit))
+        .route("/deposit-address", get(get_deposit_address))
         .route("/withdraw", post(withdraw))
+        .route("/withdraw/quote", post(quote_withdrawal))
+        .route("/withdrawals", get(get_withdrawals))
+        .route("/withdrawal-addresses", get(get_withdrawal_addresses))
+        .route("/withdrawal-addresses", post(allow_withdrawal_address))
+        .route(
+            "/withdrawal-addresses/{address}",
+            delete(remove_withdrawal_address),
+        )
         .route("/fee", post(get_aggregated_fee))
         .route("/balance", get(get_balance))
+        .route("/balances", get(get_balances))
         .route("/user/offers", get(get_user_offers))
         .route("/user/offers/{id}", delete(delete_offer))
+        .route("/ledger", get(get_ledger))
+        .route("/wallets", get(get_wallets))
+        .route("/wallets", post(link_wallet))
         .with_state(app_state.clone())
 }
 
@@ -49,35 +72,14 @@ pub async fn root(State(_state): State<AppState>, claims: Claims) -> String {
 pub async fn create_offer(
     State(state): State<AppState>,
     claims: Claims,
-    Json(payload): Json<CreateOfferRequest>,
+    Json(mut payload): Json<CreateOfferRequest>,
 ) -> Result<(), AppError> {
     println!("Creating offer");
     println!("payload: {:?}", payload);
-    state
-        .database
-        .query(
-            "
-        UPDATE transactions SET status = type::string('rejected') WHERE expiresAt < time::now() AND status = type::string('pending');
-        FOR $id IN (SELECT VALUE id FROM offers WHERE status == 'stopped') {
-                IF COUNT(SELECT * FROM transactions WHERE status='pending' AND offerId = type::thing($id)) = 0 THEN {
-                        UPDATE offers SET status = type::string('closed') WHERE id = type::thing($id);
-                } END;
-        };
-        CREATE offers SET 
-			amount = type::number($amount),
-			fee = type::number($fee),
-			cryptoType = type::string($cryptoType),
-			currency = type::string($currency), 
-			pricePerUnit = type::number($pricePerUnit), 
-			value = type::number($value),
-			offerType = type::string($offerType), 
-			revTag = type::string($revTag),
-			userId = type::thing($userId),
-			status = type::string('open');",
-        )
-        .bind(payload)
-        .bind(("userId", claims.sub))
-        .await?;
+    payload.crypto_type = state.tokens.get(&payload.crypto_type)?.symbol.clone();
+    let offer_id = offers::create(&state.database, &claims.sub, payload).await?;
+
+    println!("Offer created: {}", offer_id);
 
     Ok(())
 }
@@ -86,61 +88,127 @@ pub async fn create_transaction(
     State(state): State<AppState>,
     claims: Claims,
     Json(payload): Json<CreateTransactionRequest>,
-) -> Result<(), AppError> {
+) -> Result<(StatusCode, Json<Trade>), AppError> {
     println!("Creating transaction");
     println!("payload: {:?}", payload);
-    state
-        .database
-        .query(
-            "
-            UPDATE transactions SET status = type::string('rejected') WHERE expiresAt < time::now() AND status = type::string('pending');
-            FOR $id IN (SELECT VALUE id FROM offers WHERE status == 'stopped') {
-                    IF COUNT(SELECT * FROM transactions WHERE status='pending' AND offerId = type::thing($id)) = 0 THEN {
-                            UPDATE offers SET status = type::string('closed') WHERE id = type::thing($id);
-                    } END;
-            };
-            CREATE transactions SET 
-            offerId = type::thing($offerId), 
-            amount = type::number($amount), 
-            cryptoType = type::string($cryptoType), 
-            pricePerUnit = type::number($pricePerUnit), 
-            currency = type::string($currency), 
-            takerFee = type::number($takerFee),
-            makerFee = type::number($makerFee),
-            value = type::number($value),
-            expiresAt = time::now() + 5m, 
-            status = type::string('pending'),
-            randomTitle = type::string($randomTitle),
-            userId = type::thing($userId);
-        ",
-        )
-        .bind(payload)
-        .bind(("userId", claims.sub))
-        .await?;
+    let trade = trades::open(
+        &state.database,
+        &claims.sub,
+        payload,
+        state.trade_taker_fee_bps,
+    )
+    .await?;
 
-    println!("Transaction created");
+    println!("Transaction created: {}", trade.id);
 
-    Ok(())
+    Ok((StatusCode::CREATED, Json(trade)))
+}
+
+pub async fn get_transactions(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<Trade>>, AppError> {
+    println!("Getting transactions");
+
+    let trades = trades::list_for_user(&state.database, &claims.sub).await?;
+
+    Ok(Json(trades))
+}
+
+pub async fn get_transaction(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Trade>, AppError> {
+    println!("Getting transaction {}", id);
+
+    let trade = trades::get(&state.database, &id).await?;
+    claims.require_owner(&[&trade.user_id, &trade.maker_id])?;
+
+    Ok(Json(trade))
+}
+
+/// Called by the taker once they sent the fiat payment.
+pub async fn mark_payment_sent(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Trade>, AppError> {
+    println!("Marking payment sent for transaction {}", id);
+
+    let trade = trades::get(&state.database, &id).await?;
+    claims.require_owner(&[&trade.user_id, &trade.maker_id])?;
+    let trade = trades::mark_payment_sent(&state.database, &trade, &claims).await?;
+
+    Ok(Json(trade))
+}
+
+/// Called by the maker once they received the fiat payment. Releases the
+/// crypto to the taker.
+pub async fn confirm_payment(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Trade>, AppError> {
+    println!("Confirming payment for transaction {}", id);
+
+    let trade = trades::get(&state.database, &id).await?;
+    claims.require_owner(&[&trade.user_id, &trade.maker_id])?;
+    let trade = trades::confirm_payment(&state.database, &trade, &claims).await?;
+
+    Ok(Json(trade))
+}
+
+pub async fn cancel_transaction(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Trade>, AppError> {
+    println!("Cancelling transaction {}", id);
+
+    let trade = trades::get(&state.database, &id).await?;
+    claims.require_owner(&[&trade.user_id, &trade.maker_id])?;
+    let trade = trades::cancel(&state.database, &trade, &claims).await?;
+
+    Ok(Json(trade))
+}
+
+pub async fn dispute_transaction(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Trade>, AppError> {
+    println!("Disputing transaction {}", id);
+
+    let trade = trades::get(&state.database, &id).await?;
+    claims.require_owner(&[&trade.user_id, &trade.maker_id])?;
+    let trade = trades::dispute(&state.database, &trade, &claims).await?;
+
+    Ok(Json(trade))
 }
 
 pub async fn get_aggregated_fee(
     State(state): State<AppState>,
-    _claims: Claims,
+    claims: Claims,
     Json(payload): Json<GetAggregatedFeeRequest>,
 ) -> Result<Json<GetAggregatedFeeResponse>, AppError> {
     println!("Getting aggregated fee");
     println!("payload: {:?}", payload);
+    let offer = offers::terms(&state.database, &payload.offer_id).await?;
+    claims.require_owner(&[&offer.user_id])?;
+
     let mut response =state
         .database
         .query(
-            "MATH::SUM(SELECT VALUE makerFee FROM transactions WHERE offerId = type::thing($offerId) AND status != type::string('rejected'))",
+            "MATH::SUM(SELECT VALUE makerFee FROM transactions WHERE offerId = type::thing($offerId) AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now()))",
         )
-        .bind(("offerId", payload.offer_id))
+        .bind(("offerId", offer.id.to_string()))
         .await?;
 
     println!("Fee aggregated");
 
-    let result: Option<i128> = response.take(0).map_err(AppError::from)?;
+    let result: Option<Amount> = response.take(0).map_err(AppError::from)?;
 
     println!("Result: {:?}", result);
 
@@ -156,263 +224,253 @@ sol! {
 
     interface IERC20 {
         function transfer(address to, uint256 amount) external returns (bool);
+        function decimals() external view returns (uint8);
+        function balanceOf(address owner) external view returns (uint256);
     }
 }
 
 pub async fn confirm_deposit(
     State(state): State<AppState>,
-    _claims: Claims,
+    claims: Claims,
     Json(payload): Json<ConfirmDepositRequest>,
-) -> Result<(), AppError> {
+) -> Result<(StatusCode, Json<Deposit>), AppError> {
     println!("Confirming deposit");
     println!("payload: {:?}", payload);
 
-    let rpc_url = state.alchemy_rpc_url.parse()?;
-    let provider = ProviderBuilder::new().on_http(rpc_url);
-
-    let confirming_blocks = state.confirming_blocks;
-    let tx_hash = FixedBytes::from_str(&payload.tx_hash)?;
-
-    tokio::spawn(async move {
-        for retries in 0..15 {
-            println!("Retrying attempt {}", retries + 1);
-            println!("Tx hash: {:?}", tx_hash);
-
-            let receipt = provider.get_transaction_receipt(tx_hash).await;
-
-            println!("Receipt: {:?}", receipt);
-
-            match receipt {
-                Ok(receipt) => {
-                    if let Some(receipt) = receipt {
-                        println!("Receipt found");
-                        println!("Receipt logs: {:?}", receipt.logs());
-
-                        let block_number = receipt.block_number.ok_or_else(|| {
-                            AppError::from(anyhow::anyhow!("No block number found"))
-                        })?;
-                        let current_block = provider.get_block_number().await?;
-
-                        let maybe_log = receipt.decoded_log::<Transfer>();
-
-                        let Some(increment_log) = maybe_log else {
-                            return Err(AppError::from(anyhow::anyhow!("Increment not emitted")));
-                        };
-
-                        let Transfer { from, to, value } = increment_log.data;
-                        println!("Incremented value: {from} -> {to} = {value}");
-
-                        let decrement_log = receipt.decoded_log::<Transfer>();
-
-                        let Some(decrement_log) = decrement_log else {
-                            return Err(AppError::from(anyhow::anyhow!("Decrement not emitted")));
-                        };
-
-                        let Transfer { from, to, value } = decrement_log.data;
-                        println!("Decremented value: {from} -> {to} = {value}");
-                        println!("Confirming blocks: {}", confirming_blocks);
-                        println!("Transaction Block: {}", block_number);
-                        println!("Current Block: {}", current_block);
-
-                        if current_block - block_number >= confirming_blocks {
-                            println!("Block confirmed");
-                            println!("Receipt from: {:?}", from);
-                            println!("Amount: {:?}", value.div(U256::from(10u128.pow(12))));
-
-                            tokio::spawn(async move {
-                                let _ = state
-                                    .database
-                                    .query(
-                                        "UPDATE ONLY user SET balance = balance + type::number($amount) WHERE address = type::string($address) RETURN id, balance;",
-                                    )
-                                    .bind(("address", from.to_string().to_lowercase()))
-                                    .bind(("amount", value.div(U256::from(10u128.pow(12))).to_string()))
-                                    .await;
-
-                                println!("Balance updated for address: {}", from);
-                            });
-                            println!("Deposit confirmed!");
-                            return Ok(());
-                        }
-                    }
-                }
-                Err(err) => {
-                    println!("Error getting transaction receipt: {}", err);
-                }
-            }
-            tokio::time::sleep(Duration::from_secs(20)).await;
-        }
+    let tx_hash = TxHash::from_str(&payload.tx_hash)?;
+    let chain = match payload.chain_id {
+        Some(chain_id) => state.chains.get(chain_id)?,
+        None => state.chains.primary(),
+    };
+    let deposit = deposits::create(
+        &state.database,
+        chain.id(),
+        &claims.sub,
+        &tx_hash.to_string(),
+    )
+    .await?;
 
-        println!("Transaction confirmation failed");
-        Err(AppError::from(anyhow::anyhow!(
-            "Transaction confirmation failed after 15 attempts"
-        )))
-    });
+    println!("Deposit recorded: {:?}", deposit.id);
 
-    Ok(())
+    Ok((StatusCode::ACCEPTED, Json(deposit)))
+}
+
+pub async fn get_deposits(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<Deposit>>, AppError> {
+    println!("Getting deposits");
+
+    let deposits = deposits::list_for_user(&state.database, &claims.sub).await?;
+
+    Ok(Json(deposits))
+}
+
+pub async fn get_deposit(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(tx_hash): Path<String>,
+    Query(query): Query<GetDepositQuery>,
+) -> Result<Json<Deposit>, AppError> {
+    println!("Getting deposit {}", tx_hash);
+
+    let chain_id = query
+        .chain_id
+        .unwrap_or_else(|| state.chains.primary().id());
+    let deposit = deposits::get_for_user(&state.database, chain_id, &claims.sub, &tx_hash)
+        .await?
+        .ok_or(DepositError::NotFound(tx_hash))?;
+
+    Ok(Json(deposit))
+}
+
+pub async fn get_deposit_address(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<DepositAddressResponse>, AppError> {
+    println!("Getting deposit address");
+
+    let hd_wallet = state
+        .hd_wallet
+        .as_ref()
+        .ok_or(DepositError::AddressesNotConfigured)?;
+    let deposit_address =
+        deposits::addresses::for_user(&state.database, hd_wallet, &claims.sub).await?;
+
+    Ok(Json(DepositAddressResponse {
+        address: deposit_address.address,
+    }))
 }
 
 pub async fn withdraw(
     State(state): State<AppState>,
     claims: Claims,
     Json(payload): Json<WithdrawRequest>,
-) -> Result<(), AppError> {
+) -> Result<(StatusCode, Json<Withdrawal>), AppError> {
     println!("Withdrawing");
     println!("payload: {:?}", payload);
 
-    // Update the balance to prevent double spending
-    let mut user_balance = state
-        .database
-        .query("SELECT VALUE balance FROM user WHERE id = type::thing($id)")
-        .bind(("id", claims.sub.clone()))
-        .await?;
+    let chain_id = payload
+        .chain_id
+        .unwrap_or_else(|| state.chains.primary().id());
+    let symbol = payload
+        .token
+        .as_deref()
+        .unwrap_or(&state.tokens.default_token().symbol);
+    let token = state.tokens.get_on(chain_id, symbol)?;
+    let address = Address::from_str(&payload.address)
+        .map_err(|_| WithdrawalError::InvalidAddress(payload.address.clone()))?
+        .to_string();
+    let quote = withdrawals::fees::quote(&state, token, &address, payload.amount).await?;
+    if let Some(max_fee) = payload.max_fee {
+        if quote.fee > max_fee {
+            return Err(WithdrawalError::FeeChanged {
+                fee: quote.fee,
+                max_fee,
+            }
+            .into());
+        }
+    }
 
-    let user_balance = user_balance
-        .take::<Option<i128>>(0)
-        .map_err(AppError::from)?
-        .ok_or(AppError::from(anyhow::anyhow!("Balance not found")))?;
+    let withdrawal = withdrawals::request(
+        &state.database,
+        &state.withdrawal_limits,
+        &claims.sub,
+        &address,
+        &quote,
+    )
+    .await?;
 
-    println!("User balance: {:?}", user_balance);
+    println!("Withdrawal queued: {:?}", withdrawal.id);
 
-    if user_balance < payload.amount {
-        return Err(AppError::from(anyhow::anyhow!("Insufficient balance")));
-    }
+    Ok((StatusCode::ACCEPTED, Json(withdrawal)))
+}
 
-    let mut update_balance = state
-        .database
-        .query(
-            "UPDATE ONLY user SET balance = balance - type::number($amount) WHERE id = type::thing($id) RETURN VALUE balance;",
-        )
-        .bind(("id", claims.sub.clone()))
-        .bind(("amount", payload.amount))
-        .await?;
+pub async fn quote_withdrawal(
+    State(state): State<AppState>,
+    _claims: Claims,
+    Json(payload): Json<WithdrawQuoteRequest>,
+) -> Result<Json<WithdrawalQuote>, AppError> {
+    println!("Quoting withdrawal");
+    println!("payload: {:?}", payload);
 
-    println!("Update balance: {:?}", update_balance);
+    let chain_id = payload
+        .chain_id
+        .unwrap_or_else(|| state.chains.primary().id());
+    let symbol = payload
+        .token
+        .as_deref()
+        .unwrap_or(&state.tokens.default_token().symbol);
+    let token = state.tokens.get_on(chain_id, symbol)?;
+    let address = Address::from_str(&payload.address)
+        .map_err(|_| WithdrawalError::InvalidAddress(payload.address.clone()))?
+        .to_string();
+    let quote = withdrawals::fees::quote(&state, token, &address, payload.amount).await?;
+
+    Ok(Json(quote))
+}
 
-    let updated_balance = update_balance
-        .take::<Option<i128>>(0)
-        .map_err(AppError::from)?
-        .ok_or(AppError::from(anyhow::anyhow!("Balance not found")))?;
+pub async fn get_withdrawals(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<Withdrawal>>, AppError> {
+    println!("Getting withdrawals");
 
-    println!("Updated balance: {:?}", updated_balance);
+    let withdrawals = withdrawals::list_for_user(&state.database, &claims.sub).await?;
 
-    // Sign the transaction
-    let key_bytes: [u8; 32] = <[u8; 32]>::from_hex(&state.private_key)
-        .map_err(|_| AppError::from(anyhow::anyhow!("Invalid private key")))?;
+    Ok(Json(withdrawals))
+}
 
-    let private_key = SigningKey::from_slice(&key_bytes).unwrap();
-    let signer = PrivateKeySigner::from_signing_key(private_key);
+pub async fn get_withdrawal_addresses(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<WithdrawalAddress>>, AppError> {
+    println!("Getting withdrawal addresses");
 
-    println!("Signer: {:?}", signer);
+    let addresses = withdrawals::allowlist::list_for_user(&state.database, &claims.sub).await?;
 
-    let provider = ProviderBuilder::new()
-        .wallet(signer)
-        .on_http(state.alchemy_rpc_url.parse()?);
+    Ok(Json(addresses))
+}
 
-    println!("Provider: {:?}", provider);
+pub async fn allow_withdrawal_address(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<AllowWithdrawalAddressRequest>,
+) -> Result<(StatusCode, Json<WithdrawalAddress>), AppError> {
+    println!("Allowing withdrawal address");
+    println!("payload: {:?}", payload);
 
-    let token_address = Address::from_str(&state.token_address)?;
-    let to_address = Address::from_str(&payload.address)?;
-    let amount = U256::from(payload.amount * 10i128.pow(12));
-    let call = IERC20::transferCall {
-        to: to_address,
-        amount,
-    };
+    let address = Address::from_str(&payload.address)
+        .map_err(|_| WithdrawalError::InvalidAddress(payload.address.clone()))?
+        .to_string();
+
+    confirm_allowlist_change(
+        &state,
+        &claims,
+        &payload.message,
+        &payload.signature,
+        &withdrawals::allowlist::confirmation_statement(&address),
+    )
+    .await?;
+
+    let allowed = withdrawals::allowlist::add(
+        &state.database,
+        &claims.sub,
+        &address,
+        state.withdrawal_address_waiting_secs,
+    )
+    .await?;
+
+    println!("Withdrawal address allowed: {}", allowed.address);
+
+    Ok((StatusCode::CREATED, Json(allowed)))
+}
 
-    println!("Token address: {:?}", token_address);
-    println!("To address: {:?}", to_address);
-    println!("Amount: {:?}", amount);
+pub async fn remove_withdrawal_address(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(address): Path<String>,
+    Json(payload): Json<RemoveWithdrawalAddressRequest>,
+) -> Result<Json<WithdrawalAddress>, AppError> {
+    println!("Removing withdrawal address {}", address);
 
-    let tx = TransactionRequest::default()
-        .with_to(token_address)
-        .with_value(U256::from(0))
-        .with_call(&call);
+    let address = Address::from_str(&address)
+        .map_err(|_| WithdrawalError::InvalidAddress(address.clone()))?
+        .to_string();
 
-    let gas_estimate = provider.estimate_gas(tx.clone()).await?;
+    confirm_allowlist_change(
+        &state,
+        &claims,
+        &payload.message,
+        &payload.signature,
+        &withdrawals::allowlist::removal_statement(&address),
+    )
+    .await?;
 
-    println!("Transaction: {:?}", tx);
-    println!("Gas estimate: {:?}", gas_estimate);
+    let removed = withdrawals::allowlist::remove(&state.database, &claims.sub, &address).await?;
 
-    let tx_hash = provider
-        .send_transaction(tx.with_gas_limit(gas_estimate))
-        .await?
-        .watch()
-        .await?;
+    println!("Withdrawal address removed: {}", removed.address);
 
-    println!("Transaction sent: {:?}", tx_hash);
-
-    tokio::spawn(async move {
-        for retries in 0..15 {
-            println!("Retrying attempt {}", retries + 1);
-            println!("Tx hash: {:?}", tx_hash);
-
-            let receipt = provider.get_transaction_receipt(tx_hash).await;
-            let confirming_blocks = state.confirming_blocks;
-
-            println!("Receipt: {:?}", receipt);
-
-            match receipt {
-                Ok(receipt) => {
-                    if let Some(receipt) = receipt {
-                        println!("Receipt found");
-                        println!("Receipt logs: {:?}", receipt.logs());
-
-                        let block_number = receipt.block_number.ok_or_else(|| {
-                            AppError::from(anyhow::anyhow!("No block number found"))
-                        })?;
-                        let current_block = provider.get_block_number().await?;
-
-                        let maybe_log = receipt.decoded_log::<Transfer>();
-
-                        let Some(increment_log) = maybe_log else {
-                            return Err(AppError::from(anyhow::anyhow!("Increment not emitted")));
-                        };
-
-                        let Transfer { from, to, value } = increment_log.data;
-                        println!("Incremented value: {from} -> {to} = {value}");
-
-                        let decrement_log = receipt.decoded_log::<Transfer>();
-
-                        let Some(decrement_log) = decrement_log else {
-                            return Err(AppError::from(anyhow::anyhow!("Decrement not emitted")));
-                        };
-
-                        let Transfer { from, to, value } = decrement_log.data;
-                        println!("Decremented value: {from} -> {to} = {value}");
-                        println!("Confirming blocks: {}", confirming_blocks);
-                        println!("Transaction Block: {}", block_number);
-                        println!("Current Block: {}", current_block);
-
-                        if current_block - block_number >= confirming_blocks {
-                            println!("Block confirmed");
-                            println!("Receipt from: {:?}", from);
-                            println!("Amount: {:?}", value.div(U256::from(10u128.pow(12))));
-
-                            let _ = state
-                                    .database
-                                    .query(
-                                        "UPDATE ONLY user SET balance = balance + type::number($amount) WHERE id = type::thing($id) RETURN balance;",
-                                    )
-                                    .bind(("id", from.to_string().to_lowercase()))
-                                    .bind(("amount", value.div(U256::from(10u128.pow(12))).to_string()))
-                                    .await;
-
-                            println!("Balance updated for address: {}", from);
-                            return Ok(());
-                        }
-                    }
-                }
-                Err(err) => {
-                    println!("Error getting transaction receipt: {}", err);
-                }
-            }
+    Ok(Json(removed))
+}
 
-            tokio::time::sleep(Duration::from_secs(20)).await;
-        }
-        Err(AppError::from(anyhow::anyhow!(
-            "Transaction confirmation failed after 15 attempts"
-        )))
-    });
+/// Fails unless `message` was signed by one of the user's linked wallets and
+/// states `expected`.
+async fn confirm_allowlist_change(
+    state: &AppState,
+    claims: &Claims,
+    message: &str,
+    signature: &str,
+    expected: &str,
+) -> Result<(), AppError> {
+    let siwe_message = verify_siwe(state, message, signature).await?;
+    let signer = Address::from(siwe_message.address).to_string();
+    let confirmed = siwe_message
+        .statement
+        .is_some_and(|statement| statement.eq_ignore_ascii_case(expected));
+    if !confirmed || !wallets::is_linked(&state.database, &claims.sub, &signer).await? {
+        return Err(WithdrawalError::AllowlistNotConfirmed(expected.to_string()).into());
+    }
 
     Ok(())
 }
@@ -420,32 +478,40 @@ pub async fn withdraw(
 pub async fn get_balance(
     State(state): State<AppState>,
     claims: Claims,
+    Query(query): Query<GetBalanceQuery>,
 ) -> Result<Json<GetBalanceResponse>, AppError> {
     println!("Getting balance");
-    let mut balance = state
-        .database
-        .query("
-            LET $balance = MATH::SUM(SELECT VALUE balance FROM user WHERE walletAddress = $walletAddress);
-
-            LET $open_offers = MATH::SUM(SELECT VALUE amount + fee FROM offers WHERE userId.walletAddress = $walletAddress AND status != 'closed');
-
-            LET $closed_offers = MATH::SUM(SELECT VALUE amount + takerFee + makerFee FROM transactions WHERE offerId.userId.walletAddress = $walletAddress AND status = 'successful' AND offerId.status = 'closed');
-
-            RETURN $balance - $open_offers - $closed_offers;
-        ")
-        .bind(("id", claims.sub.clone()))
-        .await?;
+    let token = match &query.token {
+        Some(symbol) => state.tokens.registered(symbol)?,
+        None => state.tokens.default_token(),
+    };
+    let balance = ledger::available_balance(&state.database, &claims.sub, &token.symbol).await?;
 
-    println!("Response: {:?}", balance);
+    println!("Balance: {:?}", balance);
 
-    let balance = balance
-        .take::<Option<i128>>(3)
-        .map_err(AppError::from)?
-        .ok_or(AppError::from(anyhow::anyhow!("Balance not found")))?;
+    Ok(Json(GetBalanceResponse {
+        token: token.symbol.clone(),
+        balance,
+    }))
+}
 
-    println!("Balance: {:?}", balance);
+/// The available balance in every registered token.
+pub async fn get_balances(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<GetBalanceResponse>>, AppError> {
+    println!("Getting balances");
+
+    let mut balances = Vec::new();
+    for token in state.tokens.symbols() {
+        let balance = ledger::available_balance(&state.database, &claims.sub, token).await?;
+        balances.push(GetBalanceResponse {
+            token: token.to_string(),
+            balance,
+        });
+    }
 
-    Ok(Json(GetBalanceResponse { balance }))
+    Ok(Json(balances))
 }
 
 pub async fn get_user_offers(
@@ -459,12 +525,14 @@ pub async fn get_user_offers(
         .query("
             SELECT id , (amount - MATH::SUM(SELECT VALUE amount+takerFee
             FROM transactions 
-            WHERE offerId = $parent.id AND status != type::string('rejected'))) as amount, 
+            WHERE offerId = $parent.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now()))) as amount, 
             cryptoType, currency, pricePerUnit, value, offerType, revTag, fee, status
             FROM offers 
             WHERE status != type::string('closed') AND userId = type::thing($userId) AND amount - MATH::SUM(SELECT VALUE amount+takerFee
             FROM transactions 
-            WHERE offerId = $parent.id AND status != type::string('rejected')) > 0;
+            WHERE offerId = $parent.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now())) > 0;
         ")
         .bind(("userId", claims.sub.clone()))
         .await?;
@@ -480,22 +548,54 @@ pub async fn get_user_offers(
 
 pub async fn delete_offer(
     State(state): State<AppState>,
-    _claims: Claims,
+    claims: Claims,
     Path(id): Path<String>,
 ) -> Result<StatusCode, AppError> {
     println!("Deleting offer with id: {}", id);
 
-    let _ = state
-        .database
-        .query("
-            IF COUNT(SELECT * FROM transactions WHERE status='pending' AND offerId = type::thing($id)) > 0 THEN {
-					UPDATE offers SET status = type::string('stopped') WHERE id = type::thing($id);
-			} ELSE {
-					UPDATE offers SET status = type::string('closed') WHERE id = type::thing($id);
-			} END;
-        ")
-        .bind(("id", id))
-        .await?;
+    let offer = offers::terms(&state.database, &id).await?;
+    claims.require_owner(&[&offer.user_id])?;
+    offers::close(&state.database, &offer.id.to_string()).await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+pub async fn get_ledger(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<LedgerEntry>>, AppError> {
+    println!("Getting ledger history");
+
+    let entries = ledger::history(&state.database, &claims.sub).await?;
+
+    println!("Ledger entries: {:?}", entries.len());
+
+    Ok(Json(entries))
+}
+
+pub async fn get_wallets(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<Wallet>>, AppError> {
+    println!("Getting wallets");
+
+    let wallets = wallets::list_for_user(&state.database, &claims.sub).await?;
+
+    Ok(Json(wallets))
+}
+
+pub async fn link_wallet(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<LinkWalletRequest>,
+) -> Result<(StatusCode, Json<Wallet>), AppError> {
+    println!("Linking wallet");
+
+    let siwe_message = verify_siwe(&state, &payload.message, &payload.signature).await?;
+    let address = Address::from(siwe_message.address).to_string();
+    let wallet = wallets::link(&state.database, &claims.sub, &address).await?;
+
+    println!("Wallet linked: {}", wallet.address);
+
+    Ok((StatusCode::CREATED, Json(wallet)))
+}
@@ -2,12 +2,14 @@ use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use serde_with::DisplayFromStr;
 
+use crate::money::models::Amount;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateOfferRequest {
     #[serde(rename = "offerType")]
     pub offer_type: String,
-    pub amount: i128,
-    pub fee: i128,
+    pub amount: Amount,
+    pub fee: Amount,
     #[serde(rename = "cryptoType")]
     pub crypto_type: String,
     pub currency: String,
@@ -24,17 +26,9 @@ pub struct CreateTransactionRequest {
     #[serde(rename = "offerId")]
     #[serde_as(serialize_as = "DisplayFromStr")]
     pub offer_id: String,
-    pub amount: i128,
-    #[serde(rename = "cryptoType")]
-    pub crypto_type: String,
-    #[serde(rename = "pricePerUnit")]
-    pub price_per_unit: i128,
-    pub currency: String,
-    #[serde(rename = "takerFee")]
-    pub taker_fee: i128,
-    #[serde(rename = "makerFee")]
-    pub maker_fee: i128,
-    pub value: i128,
+    /// What the taker receives. Price, currency, token, value and fees all
+    /// come from the offer.
+    pub amount: Amount,
     #[serde(rename = "randomTitle")]
     pub random_title: String,
 }
@@ -51,24 +45,84 @@ pub struct GetAggregatedFeeRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetAggregatedFeeResponse {
     #[serde(rename = "aggregatedFee")]
-    pub fee: i128,
+    pub fee: Amount,
 }
 
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ConfirmDepositRequest {
+    /// The chain the deposit was sent on. Defaults to the primary chain.
+    #[serde(rename = "chainId", default)]
+    pub chain_id: Option<u64>,
     #[serde(rename = "txHash")]
     pub tx_hash: String,
-    pub amount: i128,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetDepositQuery {
+    /// The chain the deposit was sent on. Defaults to the primary chain.
+    #[serde(rename = "chainId", default)]
+    pub chain_id: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WithdrawRequest {
-    pub amount: i128,
+    /// The chain to withdraw on. Defaults to the primary chain.
+    #[serde(rename = "chainId", default)]
+    pub chain_id: Option<u64>,
+    /// Symbol of the token to withdraw. Defaults to the configured token.
+    #[serde(default)]
+    pub token: Option<String>,
+    pub amount: Amount,
+    pub address: String,
+    /// The most the user agreed to pay in fees, usually from a quote. The
+    /// withdrawal is rejected if the fee has risen above it since.
+    #[serde(rename = "maxFee", default)]
+    pub max_fee: Option<Amount>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithdrawQuoteRequest {
+    #[serde(rename = "chainId", default)]
+    pub chain_id: Option<u64>,
+    #[serde(default)]
+    pub token: Option<String>,
+    pub amount: Amount,
     pub address: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetBalanceResponse {
-    pub balance: i128,
+    pub token: String,
+    pub balance: Amount,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetBalanceQuery {
+    /// Symbol of the token. Defaults to the configured token.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkWalletRequest {
+    pub message: String,
+    pub signature: String,
+}
+
+/// `message` is a SIWE message from one of the user's linked wallets whose
+/// statement is `Allow withdrawals to <address>`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AllowWithdrawalAddressRequest {
+    pub address: String,
+    pub message: String,
+    pub signature: String,
+}
+
+/// `message` is a SIWE message from one of the user's linked wallets whose
+/// statement is `Remove withdrawals to <address>`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoveWithdrawalAddressRequest {
+    pub message: String,
+    pub signature: String,
 }
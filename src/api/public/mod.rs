@@ -1,6 +1,8 @@
 use axum::{extract::State, routing::get, Json, Router};
 use models::{DepositAddressResponse, Offer};
 
+use crate::chain::models::ChainConfig;
+use crate::tokens::models::RegisteredToken;
 use crate::AppState;
 
 use super::AppError;
@@ -12,6 +14,8 @@ pub fn router(app_state: &AppState) -> Router {
         .route("/", get(root))
         .route("/offers", get(get_offers))
         .route("/address", get(get_deposit_address))
+        .route("/tokens", get(get_tokens))
+        .route("/chains", get(get_chains))
         .with_state(app_state.clone())
 }
 
@@ -21,30 +25,27 @@ pub async fn root(State(_state): State<AppState>) -> String {
 
 pub async fn get_offers(State(state): State<AppState>) -> Result<Json<Vec<Offer>>, AppError> {
     println!("Getting offers");
+
     let mut offers = state
         .database
         .query(
             "
-            UPDATE transactions SET status = type::string('rejected') WHERE expiresAt < time::now() AND status = type::string('pending');
-            FOR $id IN (SELECT VALUE id FROM offers WHERE status == 'stopped') {
-                    IF COUNT(SELECT * FROM transactions WHERE status='pending' AND offerId = type::thing($id)) = 0 THEN {
-                            UPDATE offers SET status = type::string('closed') WHERE id = type::thing($id);
-                    } END;
-            };
             SELECT id , (amount - MATH::SUM(SELECT VALUE amount+takerFee
             FROM transactions 
-            WHERE offerId = $parent.id AND status != type::string('rejected'))) as amount, 
+            WHERE offerId = $parent.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now()))) as amount, 
             cryptoType, currency, pricePerUnit, value, offerType, revTag, fee, status
             FROM offers 
             WHERE status = type::string('open') AND amount - MATH::SUM(SELECT VALUE amount+takerFee
             FROM transactions 
-            WHERE offerId = $parent.id AND status != type::string('rejected')) > 0;
+            WHERE offerId = $parent.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now())) > 0;
         ",
         )
         .await?;
 
     println!("Offers: {:?}", offers);
-    let offers: Vec<Offer> = offers.take(2).map_err(AppError::from)?;
+    let offers: Vec<Offer> = offers.take(0).map_err(AppError::from)?;
     println!("Offers: {:?}", offers);
 
     let offers_json = Json(offers);
@@ -61,3 +62,24 @@ pub async fn get_deposit_address(
         address: deposit_address,
     }))
 }
+
+/// The tokens offers can be made in and balances held in.
+pub async fn get_tokens(State(state): State<AppState>) -> Json<Vec<RegisteredToken>> {
+    println!("Getting tokens");
+
+    Json(state.tokens.enabled().cloned().collect())
+}
+
+/// The chains deposits and withdrawals can be made on.
+pub async fn get_chains(State(state): State<AppState>) -> Json<Vec<ChainConfig>> {
+    println!("Getting chains");
+
+    Json(
+        state
+            .chains
+            .all()
+            .iter()
+            .map(|chain| chain.config.clone())
+            .collect(),
+    )
+}
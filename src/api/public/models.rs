@@ -3,6 +3,8 @@ use serde_with::serde_as;
 use serde_with::DisplayFromStr;
 use surrealdb::sql::Thing;
 
+use crate::money::models::Amount;
+
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Offer {
@@ -13,10 +15,10 @@ pub struct Offer {
     #[serde(rename = "pricePerUnit")]
     pub price_per_unit: i128,
     pub currency: String,
-    pub amount: i128,
+    pub amount: Amount,
     #[serde(rename = "cryptoType")]
     pub crypto_type: String,
-    pub fee: i128,
+    pub fee: Amount,
     pub status: String,
     pub value: i128,
     #[serde(rename = "revTag")]
@@ -1,4 +1,22 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+/// Where the hot wallet's key lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SignerKind {
+    RawKey,
+    Keystore,
+    Remote,
+}
+
+/// How withdrawal fees are charged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FeePolicyKind {
+    Flat,
+    Percentage,
+    Gas,
+}
 
 #[derive(Debug, Parser)]
 pub struct Args {
@@ -20,18 +38,181 @@ pub struct Args {
     #[arg(long, env, default_value = "secret")]
     pub jwt_secret: String,
 
-    #[arg(long, env)]
-    pub alchemy_rpc_url: String,
+    /// RPC endpoints of the primary chain, comma separated, in the order
+    /// they are tried. Other chains are configured in the `chains` table.
+    #[arg(long, env, value_delimiter = ',', required = true)]
+    pub alchemy_rpc_url: Vec<String>,
 
     #[arg(long, env, default_value = "6")]
     pub confirming_blocks: u64,
 
+    /// Average block time of the primary chain.
+    #[arg(long, env, default_value = "12")]
+    pub block_time_secs: u64,
+
+    /// Block explorer of the primary chain, e.g. `https://etherscan.io`.
+    #[arg(long, env)]
+    pub explorer_url: Option<String>,
+
+    /// How long a single request to one RPC endpoint may take.
+    #[arg(long, env, default_value = "10")]
+    pub rpc_request_timeout_secs: u64,
+
+    /// How many more rounds over every RPC endpoint of a chain are made once
+    /// all of them have failed a request.
+    #[arg(long, env, default_value = "3")]
+    pub rpc_max_retries: u32,
+
+    #[arg(long, env, default_value = "250")]
+    pub rpc_initial_backoff_ms: u64,
+
+    #[arg(long, env, default_value = "5000")]
+    pub rpc_max_backoff_ms: u64,
+
+    /// Consecutive failures after which an RPC endpoint is only used once the
+    /// others have failed.
+    #[arg(long, env, default_value = "3")]
+    pub rpc_unhealthy_after: u32,
+
+    #[arg(long, env, default_value = "30")]
+    pub rpc_health_check_interval_secs: u64,
+
+    /// How many blocks an RPC endpoint may fall behind the others before it
+    /// counts as unhealthy.
+    #[arg(long, env, default_value = "5")]
+    pub rpc_max_block_lag: u64,
+
     #[arg(long, env)]
     pub wallet_address: String,
 
+    #[arg(long, env, value_enum, default_value = "raw-key")]
+    pub withdrawal_signer: SignerKind,
+
+    /// Hex private key of the hot wallet, for the `raw-key` signer. Meant for
+    /// development only.
+    #[arg(long, env)]
+    pub private_key: Option<String>,
+
+    /// Encrypted JSON keystore of the hot wallet, for the `keystore` signer.
+    /// Its password is read from `KEYSTORE_PASSWORD`.
+    #[arg(long, env)]
+    pub keystore_path: Option<PathBuf>,
+
+    /// JSON-RPC endpoint of the `remote` signer.
     #[arg(long, env)]
-    pub private_key: String,
+    pub remote_signer_url: Option<String>,
+
+    /// Address the `remote` signer signs for.
+    #[arg(long, env)]
+    pub remote_signer_address: Option<String>,
 
     #[arg(long, env)]
     pub token_address: String,
+
+    /// Symbol the token is registered under, and what offers and
+    /// transactions refer to it as in `cryptoType`.
+    #[arg(long, env, default_value = "USDT")]
+    pub token_symbol: String,
+
+    /// Decimals of the token. Read from the contract if unset.
+    #[arg(long, env)]
+    pub token_decimals: Option<u8>,
+
+    #[arg(long, env, default_value = "20")]
+    pub deposit_poll_interval_secs: u64,
+
+    #[arg(long, env, default_value = "3600")]
+    pub deposit_timeout_secs: u64,
+
+    /// Hex-encoded BIP-32 seed that per-user deposit addresses are derived from.
+    #[arg(long, env)]
+    pub deposit_hd_seed: Option<String>,
+
+    /// Block the deposit indexer starts from when it has no checkpoint yet.
+    /// Defaults to the latest block.
+    #[arg(long, env)]
+    pub indexer_start_block: Option<u64>,
+
+    #[arg(long, env, default_value = "500")]
+    pub indexer_batch_blocks: u64,
+
+    /// How often tokens on deposit addresses are swept into the hot wallet.
+    #[arg(long, env, default_value = "300")]
+    pub sweep_interval_secs: u64,
+
+    /// Smallest balance, in balance units, a deposit address needs before
+    /// it is worth the gas to sweep.
+    #[arg(long, env, default_value = "1000000")]
+    pub sweep_min_amount: i128,
+
+    /// How often overdue trades are expired, stopped offers closed and
+    /// expired nonces purged.
+    #[arg(long, env, default_value = "30")]
+    pub expiry_interval_secs: u64,
+
+    #[arg(long, env, default_value = "15")]
+    pub withdrawal_poll_interval_secs: u64,
+
+    /// How long a withdrawal may wait in the mempool before it is replaced
+    /// with higher fees.
+    #[arg(long, env, default_value = "300")]
+    pub withdrawal_stuck_after_secs: u64,
+
+    /// How much each replacement raises the fees by, in percent. Nodes reject
+    /// replacements that raise them by less than 10.
+    #[arg(long, env, default_value = "20")]
+    pub withdrawal_fee_bump_percent: u64,
+
+    /// The most a withdrawal will ever pay per gas, in gwei.
+    #[arg(long, env, default_value = "500")]
+    pub withdrawal_max_fee_per_gas_gwei: u64,
+
+    /// Most a user may withdraw of each token over any rolling 24 hours,
+    /// unless an admin set a limit of their own. Unlimited if unset.
+    #[arg(long, env)]
+    pub withdrawal_daily_limit: Option<i128>,
+
+    /// Most a user may withdraw of each token over any rolling 30 days,
+    /// unless an admin set a limit of their own. Unlimited if unset.
+    #[arg(long, env)]
+    pub withdrawal_monthly_limit: Option<i128>,
+
+    /// Withdrawals above this amount wait for an admin to approve them,
+    /// unless an admin set a threshold for the user.
+    #[arg(long, env)]
+    pub withdrawal_approval_threshold: Option<i128>,
+
+    /// How long a newly allowed withdrawal address has to wait before it can
+    /// be used.
+    #[arg(long, env, default_value = "86400")]
+    pub withdrawal_address_waiting_secs: u64,
+
+    /// Addresses that get the admin role when they sign in.
+    #[arg(long, env, value_delimiter = ',')]
+    pub admin_addresses: Vec<String>,
+
+    #[arg(long, env, value_enum, default_value = "flat")]
+    pub withdrawal_fee_policy: FeePolicyKind,
+
+    /// Fee per withdrawal for the `flat` policy.
+    #[arg(long, env, default_value = "0")]
+    pub withdrawal_fee_flat: i128,
+
+    /// Fee for the `percentage` policy, in basis points of the amount.
+    #[arg(long, env, default_value = "0")]
+    pub withdrawal_fee_bps: u32,
+
+    /// Fee charged to the taker of a trade, in basis points of the amount
+    /// traded. Taken from the offer along with the amount.
+    #[arg(long, env, default_value = "0")]
+    pub trade_taker_fee_bps: u32,
+
+    /// Price of one ETH in balance units, for the `gas` policy.
+    #[arg(long, env)]
+    pub withdrawal_fee_eth_price: Option<i128>,
+
+    /// How much the `gas` policy marks the estimated gas cost up by, in
+    /// percent.
+    #[arg(long, env, default_value = "20")]
+    pub withdrawal_fee_gas_margin_percent: u32,
 }
@@ -0,0 +1,56 @@
+//! Confirmation tracking that survives chain reorganisations.
+
+use alloy::eips::BlockNumberOrTag;
+use alloy::primitives::BlockHash;
+use alloy::providers::Provider;
+use alloy::transports::TransportResult;
+
+pub mod models;
+pub mod registry;
+
+/// How deep a transaction is buried, judged against the current canonical chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// Still in the canonical chain, not yet deep enough.
+    Pending { confirmations: u64 },
+    /// Still in the canonical chain and at least `required` blocks deep.
+    Confirmed { confirmations: u64 },
+    /// The block it was mined in is no longer canonical.
+    Reorged,
+}
+
+/// Whether `block_hash` is still the canonical block at `block_number`.
+pub async fn is_canonical(
+    provider: &impl Provider,
+    block_number: u64,
+    block_hash: BlockHash,
+) -> TransportResult<bool> {
+    let block = provider
+        .get_block_by_number(BlockNumberOrTag::Number(block_number))
+        .await?;
+
+    Ok(block.is_some_and(|block| block.header.hash == block_hash))
+}
+
+/// Checks a transaction mined in `block_hash` at `block_number` against the
+/// canonical chain. A provider that lags behind the block we saw counts as
+/// zero confirmations rather than underflowing.
+pub async fn confirmation(
+    provider: &impl Provider,
+    block_number: u64,
+    block_hash: BlockHash,
+    required: u64,
+) -> TransportResult<Confirmation> {
+    let current_block = provider.get_block_number().await?;
+
+    if !is_canonical(provider, block_number, block_hash).await? {
+        return Ok(Confirmation::Reorged);
+    }
+
+    let confirmations = current_block.saturating_sub(block_number);
+    if confirmations >= required {
+        return Ok(Confirmation::Confirmed { confirmations });
+    }
+
+    Ok(Confirmation::Pending { confirmations })
+}
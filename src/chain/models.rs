@@ -0,0 +1,66 @@
+use alloy::transports::TransportError;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::rpc::models::RpcError;
+
+/// A row of the `chains` table. The RPC URLs are never sent to clients, since
+/// they usually carry an API key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainConfig {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    #[serde(rename = "rpcUrls", default, skip_serializing)]
+    pub rpc_urls: Vec<String>,
+    /// How deep a transaction has to be before it is final.
+    pub confirmations: u64,
+    #[serde(rename = "blockTimeSecs")]
+    pub block_time_secs: u64,
+    /// Base URL of a block explorer, e.g. `https://etherscan.io`.
+    #[serde(rename = "explorerUrl")]
+    pub explorer_url: Option<String>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum ChainError {
+    #[error("chain {0} is not supported")]
+    Unknown(u64),
+
+    #[error("chain {chain_id}: {source}")]
+    Endpoints { chain_id: u64, source: RpcError },
+
+    #[error("RPC of chain {expected} is connected to chain {actual}")]
+    ChainIdMismatch { expected: u64, actual: u64 },
+
+    #[error(transparent)]
+    Rpc(#[from] Box<TransportError>),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<TransportError> for ChainError {
+    fn from(error: TransportError) -> Self {
+        ChainError::Rpc(Box::new(error))
+    }
+}
+
+impl From<surrealdb::Error> for ChainError {
+    fn from(error: surrealdb::Error) -> Self {
+        ChainError::Surrealdb(Box::new(error))
+    }
+}
+
+impl ChainError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ChainError::Unknown(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ChainError::Endpoints { .. }
+            | ChainError::ChainIdMismatch { .. }
+            | ChainError::Rpc(_)
+            | ChainError::Surrealdb(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! The chains deposits and withdrawals can be made on.
+//!
+//! Chains are rows of the `chains` table, keyed by chain id. The chain
+//! `--alchemy-rpc-url` points at is always registered and enabled, with the
+//! confirmation settings given on the command line, and is the chain of every
+//! record written before there was more than one. Other chains are added to
+//! the table directly, with their own RPC URLs, and picked up on the next
+//! start. Disabled chains are not connected to at all.
+
+use std::sync::Arc;
+
+use alloy::providers::{DynProvider, Provider};
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use super::models::{ChainConfig, ChainError};
+use crate::hot_wallet::signer::WithdrawalSigner;
+use crate::hot_wallet::HotWallet;
+use crate::rpc::models::RetryPolicy;
+use crate::rpc::RpcPool;
+
+/// An enabled chain, connected through its RPC pool. The hot wallet has the
+/// same address on every chain, but keeps its nonces per chain.
+#[derive(Debug, Clone)]
+pub struct Chain {
+    pub config: ChainConfig,
+    pub rpc: RpcPool,
+    pub provider: DynProvider,
+    pub hot_wallet: HotWallet,
+}
+
+impl Chain {
+    pub fn id(&self) -> u64 {
+        self.config.chain_id
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChainRegistry {
+    chains: Arc<Vec<Chain>>,
+}
+
+impl ChainRegistry {
+    /// Registers `primary`, already connected through `rpc`, and connects to
+    /// every other enabled chain under `policy`. Withdrawals on all of them
+    /// are signed by `signer`.
+    pub async fn load(
+        db: &Surreal<Any>,
+        primary: ChainConfig,
+        rpc: RpcPool,
+        policy: RetryPolicy,
+        signer: Arc<dyn WithdrawalSigner>,
+    ) -> Result<Self, ChainError> {
+        db.query(
+            "
+            UPSERT type::thing('chains', $chainId) SET
+                chainId = $chainId,
+                confirmations = $confirmations,
+                blockTimeSecs = $blockTimeSecs,
+                explorerUrl = $explorerUrl,
+                enabled = true;
+            ",
+        )
+        .bind(("chainId", primary.chain_id))
+        .bind(("confirmations", primary.confirmations))
+        .bind(("blockTimeSecs", primary.block_time_secs))
+        .bind(("explorerUrl", primary.explorer_url.clone()))
+        .await?
+        .check()?;
+
+        let records = db
+            .query(
+                "
+                SELECT chainId, rpcUrls, confirmations, blockTimeSecs, explorerUrl, enabled
+                FROM chains WHERE enabled = true AND chainId != $chainId;
+                ",
+            )
+            .bind(("chainId", primary.chain_id))
+            .await?
+            .take::<Vec<ChainConfig>>(0)?;
+
+        let mut chains = Vec::with_capacity(records.len() + 1);
+        let provider = rpc.provider();
+        chains.push(Chain {
+            hot_wallet: HotWallet::new(signer.clone(), provider.clone(), primary.chain_id),
+            config: primary,
+            rpc,
+            provider,
+        });
+        for config in records {
+            let rpc = connect(&config, policy).await?;
+            let provider = rpc.provider();
+            chains.push(Chain {
+                hot_wallet: HotWallet::new(signer.clone(), provider.clone(), config.chain_id),
+                config,
+                rpc,
+                provider,
+            });
+        }
+
+        for chain in &chains {
+            println!(
+                "Registered chain {} with {} RPC endpoints and {} confirmations",
+                chain.id(),
+                chain.config.rpc_urls.len(),
+                chain.config.confirmations
+            );
+        }
+
+        Ok(ChainRegistry {
+            chains: Arc::new(chains),
+        })
+    }
+
+    /// The configured chain, which older records are on.
+    pub fn primary(&self) -> &Chain {
+        &self.chains[0]
+    }
+
+    /// An enabled chain by its id.
+    pub fn get(&self, chain_id: u64) -> Result<&Chain, ChainError> {
+        self.chains
+            .iter()
+            .find(|chain| chain.id() == chain_id)
+            .ok_or(ChainError::Unknown(chain_id))
+    }
+
+    pub fn all(&self) -> &[Chain] {
+        &self.chains
+    }
+}
+
+/// Sets up the RPC pool of `config` and checks that it serves the chain it
+/// is supposed to.
+async fn connect(config: &ChainConfig, policy: RetryPolicy) -> Result<RpcPool, ChainError> {
+    let rpc = RpcPool::new(&config.rpc_urls, policy).map_err(|source| ChainError::Endpoints {
+        chain_id: config.chain_id,
+        source,
+    })?;
+
+    let actual = rpc.provider().get_chain_id().await?;
+    if actual != config.chain_id {
+        return Err(ChainError::ChainIdMismatch {
+            expected: config.chain_id,
+            actual,
+        });
+    }
+
+    Ok(rpc)
+}
+
+/// Assigns the primary chain to withdrawals and custody ledger legs written
+/// before records carried a chain, and moves the hot wallet nonces kept per
+/// address to the chain.
+pub async fn migrate_unchained(db: &Surreal<Any>, chain_id: u64) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE withdrawals SET chainId = $chainId WHERE chainId = NONE;
+        UPDATE ledger SET chainId = $chainId WHERE account = 'custody' AND chainId = NONE;
+        FOR $nonce IN (SELECT id, next FROM nonces WHERE type::is::string(record::id(id))) {
+            UPSERT type::thing('nonces', [$chainId, record::id($nonce.id)]) SET
+                next = math::max([next OR 0, $nonce.next]),
+                updatedAt = time::now();
+            DELETE $nonce.id;
+        };
+        ",
+    )
+    .bind(("chainId", chain_id))
+    .await?
+    .check()?;
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Schema definitions and helpers for reading SurrealDB errors.
+
+use std::time::Duration;
+
+use rand::Rng;
+use surrealdb::engine::any::Any;
+use surrealdb::{Response, Surreal};
+
+/// How often a transaction that lost a write conflict is retried.
+pub const CONFLICT_RETRIES: usize = 3;
+
+/// How long the first retry after a write conflict waits at most; doubled on
+/// every retry after it.
+const CONFLICT_BACKOFF: Duration = Duration::from_millis(20);
+
+/// Unique indexes the server relies on. Safe to run on every startup.
+pub async fn define_schema(db: &Surreal<Any>) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        DEFINE INDEX IF NOT EXISTS journal_reference ON journal FIELDS kind, reference UNIQUE;
+        REMOVE INDEX IF EXISTS deposits_tx ON deposits;
+        DEFINE INDEX IF NOT EXISTS deposits_tx_user ON deposits FIELDS chainId, txHash, userId UNIQUE;
+        DEFINE INDEX IF NOT EXISTS wallets_address ON wallets FIELDS address UNIQUE;
+        DEFINE INDEX IF NOT EXISTS deposit_addresses_user ON deposit_addresses FIELDS userId UNIQUE;
+        DEFINE INDEX IF NOT EXISTS deposit_addresses_address ON deposit_addresses FIELDS address UNIQUE;
+        DEFINE INDEX IF NOT EXISTS tokens_chain_address ON tokens FIELDS chainId, address UNIQUE;
+        DEFINE INDEX IF NOT EXISTS withdrawal_addresses_user_address ON withdrawal_addresses FIELDS userId, address UNIQUE;
+        ",
+    )
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Whether `error` was raised because a write hit the unique `index`.
+pub fn violates_index(error: &surrealdb::Error, index: &str) -> bool {
+    error
+        .to_string()
+        .contains(&format!("index `{}` already contains", index))
+}
+
+/// Whether a transaction failed because a concurrent one wrote the same rows.
+pub fn is_conflict(error: &surrealdb::Error) -> bool {
+    error.to_string().contains("can be retried")
+}
+
+/// Waits before retry `attempt` of a transaction that lost a write conflict,
+/// counting from 1. A random half of the wait is taken off, so transactions
+/// that conflicted with each other do not retry in lockstep.
+pub async fn conflict_backoff(attempt: usize) {
+    let exponential =
+        CONFLICT_BACKOFF.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1) as u32));
+    let half = exponential / 2;
+    let wait = half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0));
+
+    tokio::time::sleep(wait).await;
+}
+
+/// Takes the error that made a query fail, if any. When a transaction fails
+/// every statement in it reports an error, but only one of them says why.
+pub fn take_error(response: &mut Response) -> Option<surrealdb::Error> {
+    let mut errors: Vec<_> = response.take_errors().into_iter().collect();
+    errors.sort_by_key(|(index, _)| *index);
+
+    let cause = errors
+        .iter()
+        .position(|(_, error)| !error.to_string().contains("failed transaction"))
+        .unwrap_or(0);
+
+    (!errors.is_empty()).then(|| errors.swap_remove(cause).1)
+}
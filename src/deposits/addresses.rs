@@ -0,0 +1,111 @@
+//! Deposit addresses derived for each user from the server's HD wallet.
+//!
+//! Tokens sent to a user's deposit address belong to that user, whoever sent
+//! them. The funds stay on the derived address until the [`sweeper`] moves
+//! them to the hot wallet.
+//!
+//! [`sweeper`]: super::sweeper
+
+use alloy::primitives::Address;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+use super::models::{DepositAddress, DepositError};
+use crate::db;
+use crate::hd_wallet::HdWallet;
+
+/// The user's deposit address, deriving and storing a new one the first time.
+pub async fn for_user(
+    db: &Surreal<Any>,
+    hd_wallet: &HdWallet,
+    user_id: &str,
+) -> Result<DepositAddress, DepositError> {
+    if let Some(existing) = of_user(db, user_id).await? {
+        return Ok(existing);
+    }
+
+    let index = db
+        .query("UPSERT ONLY counters:deposit_addresses SET value = (value OR 0) + 1 RETURN VALUE value")
+        .await?
+        .take::<Option<u32>>(0)?
+        .ok_or(DepositError::AddressNotAllocated)?
+        - 1;
+    let address = hd_wallet.address(index)?;
+
+    println!("Derived deposit address {} at index {}", address, index);
+
+    let mut response = db
+        .query(
+            "
+            CREATE ONLY deposit_addresses SET
+                userId = type::thing($userId),
+                index = $index,
+                address = type::string($address),
+                createdAt = time::now();
+            ",
+        )
+        .bind(("userId", user_id.to_string()))
+        .bind(("index", index))
+        .bind(("address", address.to_string().to_lowercase()))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        // Another request derived an address for this user first; the index
+        // we took is simply left unused.
+        if db::violates_index(&error, "deposit_addresses_user") {
+            return of_user(db, user_id)
+                .await?
+                .ok_or(DepositError::AddressNotAllocated);
+        }
+        return Err(error.into());
+    }
+
+    response
+        .take::<Option<DepositAddress>>(0)?
+        .ok_or(DepositError::AddressNotAllocated)
+}
+
+pub async fn of_user(
+    db: &Surreal<Any>,
+    user_id: &str,
+) -> Result<Option<DepositAddress>, DepositError> {
+    let address = db
+        .query("SELECT * FROM deposit_addresses WHERE userId = type::thing($userId)")
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Option<DepositAddress>>(0)?;
+
+    Ok(address)
+}
+
+/// The user a deposit address was derived for, if any.
+pub async fn owner(db: &Surreal<Any>, address: Address) -> Result<Option<Thing>, DepositError> {
+    let owner = db
+        .query("SELECT VALUE userId FROM deposit_addresses WHERE address = type::string($address)")
+        .bind(("address", address.to_string().to_lowercase()))
+        .await?
+        .take::<Option<Thing>>(0)?;
+
+    Ok(owner)
+}
+
+/// Every deposit address derived so far, with the index it was derived at.
+pub async fn list(db: &Surreal<Any>) -> Result<Vec<DepositAddress>, DepositError> {
+    let addresses = db
+        .query("SELECT * FROM deposit_addresses ORDER BY index ASC")
+        .await?
+        .take::<Vec<DepositAddress>>(0)?;
+
+    Ok(addresses)
+}
+
+/// Every deposit address derived so far.
+pub async fn all(db: &Surreal<Any>) -> Result<Vec<String>, DepositError> {
+    let addresses = db
+        .query("SELECT VALUE address FROM deposit_addresses")
+        .await?
+        .take::<Vec<String>>(0)?;
+
+    Ok(addresses)
+}
@@ -0,0 +1,466 @@
+//! Discovers deposits on-chain so users do not have to submit a hash.
+//!
+//! The indexer pages through `eth_getLogs` for Transfers of any enabled token
+//! into the shared wallet or any per-user deposit address, records a deposit
+//! for each user a transaction pays, and checkpoints the last block it
+//! processed. Confirmation and crediting are left to the deposit [`worker`].
+//!
+//! One indexer runs per chain, each with its own checkpoint.
+//!
+//! To run it against a local Anvil node, point `ALCHEMY_RPC_URL` at Anvil and
+//! set `INDEXER_START_BLOCK=0`.
+//!
+//! [`worker`]: super::worker
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::time::Duration;
+
+use alloy::eips::BlockNumberOrTag;
+use alloy::primitives::{Address, BlockHash, B256};
+use alloy::providers::Provider;
+use alloy::rpc::types::{Filter, Log};
+use alloy::sol_types::SolEvent;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::any::Any;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+use super::addresses;
+use super::models::DepositError;
+use crate::api::private::Transfer;
+use crate::{chain, wallets, AppState};
+
+/// Where the primary chain's checkpoint was kept when there was only one.
+const LEGACY_CHECKPOINT: &str = "indexer:transfers";
+
+/// The last block the indexer processed, and its hash at the time.
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    #[serde(rename = "lastBlock")]
+    last_block: u64,
+    #[serde(rename = "blockHash")]
+    block_hash: String,
+}
+
+pub async fn run(state: AppState, chain_id: u64) -> anyhow::Result<()> {
+    let db = &state.database;
+    let chain = state.chains.get(chain_id)?;
+    let provider = &chain.provider;
+    // Nothing new can show up before the next block.
+    let poll_interval = Duration::from_secs(chain.config.block_time_secs.max(1));
+    let tokens: Vec<Address> = state
+        .tokens
+        .enabled()
+        .filter(|token| token.chain_id == chain_id)
+        .map(|token| token.address)
+        .collect();
+    let wallet = Address::from_str(&state.wallet_address)?;
+    if tokens.is_empty() {
+        println!("No tokens enabled on chain {}, not indexing", chain_id);
+        return Ok(());
+    }
+
+    let checkpoint_id = format!("indexer:transfers_{}", chain_id);
+    let mut checkpoint = load_checkpoint(db, &checkpoint_id).await?;
+    if checkpoint.is_none() && chain_id == state.chains.primary().id() {
+        checkpoint = load_checkpoint(db, LEGACY_CHECKPOINT).await?;
+    }
+    let mut next_block = match &checkpoint {
+        Some(checkpoint) => checkpoint.last_block + 1,
+        None => match state.indexer_start_block {
+            Some(block) => block,
+            None => provider.get_block_number().await?,
+        },
+    };
+
+    println!(
+        "Indexer started at block {} on chain {}",
+        next_block, chain_id
+    );
+
+    loop {
+        // The logs have to come from an endpoint that has the blocks it
+        // reported as latest.
+        let provider = &chain.rpc.pinned();
+
+        // If the last block we indexed was reorged out, the Transfers in the
+        // blocks replacing it were never seen. Step back and index them again;
+        // recording a deposit twice is a no-op.
+        if let Some(last) = &checkpoint {
+            let last_hash = BlockHash::from_str(&last.block_hash)?;
+            if !chain::is_canonical(provider, last.last_block, last_hash).await? {
+                next_block = last.last_block.saturating_sub(chain.config.confirmations);
+                println!(
+                    "Block {} was reorged out, reindexing from {}",
+                    last_hash, next_block
+                );
+                checkpoint = None;
+            }
+        }
+
+        let latest_block = provider.get_block_number().await?;
+        if next_block > latest_block {
+            tokio::time::sleep(poll_interval).await;
+            continue;
+        }
+
+        let to_block = latest_block.min(next_block.saturating_add(state.indexer_batch_blocks - 1));
+
+        let mut recipients = vec![wallet];
+        for address in addresses::all(db).await? {
+            recipients.push(Address::from_str(&address)?);
+        }
+
+        let filter = transfers_to(&tokens, &recipients)
+            .from_block(next_block)
+            .to_block(to_block);
+
+        let logs = provider.get_logs(&filter).await?;
+        println!(
+            "Indexed blocks {}..={} on chain {}: {} transfers",
+            next_block,
+            to_block,
+            chain_id,
+            logs.len()
+        );
+
+        record(db, chain_id, wallet, chain.hot_wallet.address(), &logs).await?;
+
+        let Some(block) = provider
+            .get_block_by_number(BlockNumberOrTag::Number(to_block))
+            .await?
+        else {
+            continue;
+        };
+        let saved = Checkpoint {
+            last_block: to_block,
+            block_hash: block.header.hash.to_string(),
+        };
+        save_checkpoint(db, &checkpoint_id, &saved).await?;
+        checkpoint = Some(saved);
+        next_block = to_block + 1;
+    }
+}
+
+/// Matches Transfers of any of `tokens` into any of `recipients`.
+fn transfers_to(tokens: &[Address], recipients: &[Address]) -> Filter {
+    Filter::new()
+        .address(tokens.to_vec())
+        .event_signature(Transfer::SIGNATURE_HASH)
+        .topic2(
+            recipients
+                .iter()
+                .map(|recipient| recipient.into_word())
+                .collect::<Vec<B256>>(),
+        )
+}
+
+/// Records a deposit for every user a Transfer in `logs` pays, once per
+/// transaction: a transaction with several transfers to the same user is one
+/// deposit, and a batch payout to several users is one for each of them.
+/// Sweeps from a deposit address into `hot_wallet` are not deposits.
+async fn record(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    wallet: Address,
+    hot_wallet: Address,
+    logs: &[Log],
+) -> anyhow::Result<()> {
+    let mut deposits = BTreeSet::new();
+    for log in logs.iter().filter(|log| !log.removed) {
+        let (Some(tx_hash), Ok(decoded)) = (log.transaction_hash, log.log_decode::<Transfer>())
+        else {
+            continue;
+        };
+        let Transfer { from, to, .. } = decoded.inner.data;
+        // The sweeper moving funds out of a deposit address, not a deposit.
+        // Anything else a deposit address sends is paid to whoever receives it.
+        if to == hot_wallet && addresses::owner(db, from).await?.is_some() {
+            continue;
+        }
+        let Some(user_id) = attribute(db, wallet, from, to).await? else {
+            println!("Transfer {} from {} cannot be attributed", tx_hash, from);
+            continue;
+        };
+        deposits.insert((tx_hash, user_id.to_string()));
+    }
+
+    for (tx_hash, user_id) in deposits {
+        let tx_hash = tx_hash.to_string();
+        match super::create(db, chain_id, &user_id, &tx_hash).await {
+            Ok(deposit) => println!("Discovered deposit {} for {}", tx_hash, deposit.user_id),
+            Err(DepositError::AlreadyClaimed(_) | DepositError::AlreadyCredited(_)) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// The user a Transfer belongs to: the owner of the deposit address it was
+/// sent to, or for the shared wallet, the user who linked the sending wallet.
+async fn attribute(
+    db: &Surreal<Any>,
+    wallet: Address,
+    from: Address,
+    to: Address,
+) -> anyhow::Result<Option<Thing>> {
+    if to == wallet {
+        return Ok(wallets::owner(db, &from.to_string()).await?);
+    }
+
+    Ok(addresses::owner(db, to).await?)
+}
+
+async fn load_checkpoint(
+    db: &Surreal<Any>,
+    id: &str,
+) -> Result<Option<Checkpoint>, surrealdb::Error> {
+    db.query("SELECT lastBlock, blockHash FROM ONLY type::thing($id)")
+        .bind(("id", id.to_string()))
+        .await?
+        .take::<Option<Checkpoint>>(0)
+}
+
+async fn save_checkpoint(
+    db: &Surreal<Any>,
+    id: &str,
+    checkpoint: &Checkpoint,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "UPSERT type::thing($id) SET lastBlock = $lastBlock, blockHash = type::string($blockHash), updatedAt = time::now()",
+    )
+    .bind(("id", id.to_string()))
+    .bind(("lastBlock", checkpoint.last_block))
+    .bind(("blockHash", checkpoint.block_hash.clone()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::network::{EthereumWallet, TransactionBuilder};
+    use alloy::node_bindings::Anvil;
+    use alloy::primitives::{Bytes, TxHash, U256};
+    use alloy::providers::ProviderBuilder;
+    use alloy::rpc::types::TransactionRequest;
+    use alloy::signers::local::PrivateKeySigner;
+    use surrealdb::engine::any;
+
+    use super::*;
+    use crate::db;
+    use crate::deposits::models::Deposit;
+
+    async fn memory_db() -> Surreal<Any> {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        db
+    }
+
+    async fn assign_deposit_address(
+        db: &Surreal<Any>,
+        user_id: &str,
+        index: u32,
+        address: Address,
+    ) {
+        db.query(
+            "
+            CREATE deposit_addresses SET
+                userId = type::thing($userId),
+                index = $index,
+                address = type::string($address),
+                createdAt = time::now();
+            ",
+        )
+        .bind(("userId", user_id.to_string()))
+        .bind(("index", index))
+        .bind(("address", address.to_string().to_lowercase()))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+    }
+
+    async fn deposits_of(db: &Surreal<Any>, tx_hash: TxHash) -> Vec<Deposit> {
+        db.query("SELECT * FROM deposits WHERE txHash = type::string($txHash) ORDER BY userId")
+            .bind(("txHash", tx_hash.to_string()))
+            .await
+            .unwrap()
+            .take::<Vec<Deposit>>(0)
+            .unwrap()
+    }
+
+    fn transfer_log(
+        token: Address,
+        tx_hash: TxHash,
+        log_index: u64,
+        from: Address,
+        to: Address,
+    ) -> Log {
+        let transfer = Transfer {
+            from,
+            to,
+            value: U256::from(1_000_000),
+        };
+
+        Log {
+            inner: alloy::primitives::Log {
+                address: token,
+                data: transfer.encode_log_data(),
+            },
+            transaction_hash: Some(tx_hash),
+            log_index: Some(log_index),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_payouts_are_a_deposit_for_each_user() {
+        let db = memory_db().await;
+        let wallet = Address::repeat_byte(0x01);
+        let token = Address::repeat_byte(0x02);
+        let (alice, bob) = (Address::repeat_byte(0x0a), Address::repeat_byte(0x0b));
+        assign_deposit_address(&db, "user:alice", 0, alice).await;
+        assign_deposit_address(&db, "user:bob", 1, bob).await;
+
+        let payer = Address::repeat_byte(0xee);
+        let tx_hash = TxHash::repeat_byte(0x11);
+        let logs = [
+            transfer_log(token, tx_hash, 0, payer, alice),
+            transfer_log(token, tx_hash, 1, payer, bob),
+            transfer_log(token, tx_hash, 2, payer, alice),
+        ];
+        record(&db, 1, wallet, wallet, &logs).await.unwrap();
+        // Indexing the same blocks again after a reorg records nothing new.
+        record(&db, 1, wallet, wallet, &logs).await.unwrap();
+
+        let users: Vec<String> = deposits_of(&db, tx_hash)
+            .await
+            .iter()
+            .map(|deposit| deposit.user_id.to_string())
+            .collect();
+        assert_eq!(users, ["user:alice", "user:bob"]);
+    }
+
+    #[tokio::test]
+    async fn only_sweeps_into_the_hot_wallet_are_skipped() {
+        let db = memory_db().await;
+        let wallet = Address::repeat_byte(0x01);
+        let token = Address::repeat_byte(0x02);
+        let (alice, bob) = (Address::repeat_byte(0x0a), Address::repeat_byte(0x0b));
+        assign_deposit_address(&db, "user:alice", 0, alice).await;
+        assign_deposit_address(&db, "user:bob", 1, bob).await;
+        // Alice's deposit address was linked as her wallet, so anything it
+        // sends to the shared wallet would otherwise be hers.
+        wallets::link(&db, "user:alice", &alice.to_string())
+            .await
+            .unwrap();
+
+        let payment = TxHash::repeat_byte(0x11);
+        let sweep = TxHash::repeat_byte(0x12);
+        let logs = [
+            transfer_log(token, payment, 0, alice, bob),
+            transfer_log(token, sweep, 0, alice, wallet),
+        ];
+        record(&db, 1, wallet, wallet, &logs).await.unwrap();
+
+        let paid = deposits_of(&db, payment).await;
+        assert_eq!(paid.len(), 1);
+        assert_eq!(paid[0].user_id.to_string(), "user:bob");
+        assert!(deposits_of(&db, sweep).await.is_empty());
+    }
+
+    /// Runtime code of a token whose every call is a transfer from the caller:
+    /// it logs `Transfer(caller, to, amount)` for the `transfer(to, amount)`
+    /// arguments and returns true, without keeping any balances.
+    fn token_runtime() -> Vec<u8> {
+        let mut code = vec![
+            0x60, 0x24, 0x35, // PUSH1 0x24 CALLDATALOAD: amount
+            0x60, 0x00, 0x52, // PUSH1 0x00 MSTORE
+            0x60, 0x04, 0x35, // PUSH1 0x04 CALLDATALOAD: to
+            0x33, // CALLER: from
+            0x7f, // PUSH32 the event signature
+        ];
+        code.extend_from_slice(Transfer::SIGNATURE_HASH.as_slice());
+        code.extend_from_slice(&[
+            0x60, 0x20, 0x60, 0x00, 0xa3, // LOG3 of the amount
+            0x60, 0x01, 0x60, 0x00, 0x52, // MSTORE true
+            0x60, 0x20, 0x60, 0x00, 0xf3, // RETURN it
+        ]);
+        code
+    }
+
+    /// Deploy code that returns `runtime` as the contract's code.
+    fn deploy_code(runtime: &[u8]) -> Bytes {
+        let length = runtime.len() as u8;
+        let mut code = vec![
+            0x60, length, 0x60, 0x0c, 0x60, 0x00, 0x39, // CODECOPY the runtime
+            0x60, length, 0x60, 0x00, 0xf3, // RETURN it
+        ];
+        code.extend_from_slice(runtime);
+        Bytes::from(code)
+    }
+
+    /// Needs `anvil` on the `PATH`.
+    #[tokio::test]
+    #[ignore]
+    async fn indexes_a_token_transfer_on_anvil() {
+        let anvil = Anvil::new().spawn();
+        let sender = PrivateKeySigner::from(anvil.keys()[0].clone());
+        let provider = ProviderBuilder::new()
+            .wallet(EthereumWallet::from(sender))
+            .on_http(anvil.endpoint_url());
+        let db = memory_db().await;
+        let wallet = Address::repeat_byte(0x01);
+        let deposit_address = Address::repeat_byte(0x0a);
+        assign_deposit_address(&db, "user:alice", 0, deposit_address).await;
+
+        let deployed = provider
+            .send_transaction(
+                TransactionRequest::default().with_deploy_code(deploy_code(&token_runtime())),
+            )
+            .await
+            .unwrap()
+            .get_receipt()
+            .await
+            .unwrap();
+        let token = deployed.contract_address.unwrap();
+
+        let mut calldata = vec![0xa9, 0x05, 0x9c, 0xbb];
+        calldata.extend_from_slice(deposit_address.into_word().as_slice());
+        calldata.extend_from_slice(&U256::from(1_000_000).to_be_bytes::<32>());
+        let transferred = provider
+            .send_transaction(
+                TransactionRequest::default()
+                    .with_to(token)
+                    .with_input(calldata),
+            )
+            .await
+            .unwrap()
+            .get_receipt()
+            .await
+            .unwrap();
+
+        let latest = provider.get_block_number().await.unwrap();
+        let logs = provider
+            .get_logs(
+                &transfers_to(&[token], &[wallet, deposit_address])
+                    .from_block(0)
+                    .to_block(latest),
+            )
+            .await
+            .unwrap();
+        record(&db, anvil.chain_id(), wallet, wallet, &logs)
+            .await
+            .unwrap();
+
+        let deposits = deposits_of(&db, transferred.transaction_hash).await;
+        assert_eq!(deposits.len(), 1);
+        assert_eq!(deposits[0].user_id.to_string(), "user:alice");
+        assert_eq!(deposits[0].chain_id, anvil.chain_id());
+    }
+}
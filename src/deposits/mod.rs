@@ -0,0 +1,356 @@
+//! Deposits submitted by users and tracked until they are credited.
+//!
+//! Deposits are recorded either by a handler when a user submits a hash or by
+//! the [`indexer`] when it finds one on-chain; the [`worker`] picks up every
+//! pending deposit, including the ones left over from before a restart, and
+//! moves it through its statuses.
+
+use models::{Deposit, DepositError, DepositStatus, TokenAmount};
+
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use crate::db;
+
+pub mod addresses;
+pub mod indexer;
+pub mod models;
+pub mod sweeper;
+pub mod verifier;
+pub mod worker;
+
+/// Records a deposit claimed by a user. Each user can claim a transaction
+/// once per chain; a batch payout to several users is a deposit for each.
+pub async fn create(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    user_id: &str,
+    tx_hash: &str,
+) -> Result<Deposit, DepositError> {
+    let tx_hash = tx_hash.to_lowercase();
+
+    let existing = db
+        .query(
+            "
+            SELECT * FROM deposits
+            WHERE chainId = $chainId AND txHash = type::string($txHash) AND userId = type::thing($userId);
+            ",
+        )
+        .bind(("chainId", chain_id))
+        .bind(("txHash", tx_hash.clone()))
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Option<Deposit>>(0)?;
+
+    if let Some(existing) = existing {
+        return Err(match existing.status {
+            DepositStatus::Confirmed => DepositError::AlreadyCredited(tx_hash),
+            _ => DepositError::AlreadyClaimed(tx_hash),
+        });
+    }
+
+    let mut response = db
+        .query(
+            "
+            CREATE ONLY deposits SET
+                chainId = $chainId,
+                txHash = type::string($txHash),
+                userId = type::thing($userId),
+                status = type::string($status),
+                confirmations = 0,
+                createdAt = time::now(),
+                updatedAt = time::now();
+            ",
+        )
+        .bind(("chainId", chain_id))
+        .bind(("txHash", tx_hash.clone()))
+        .bind(("userId", user_id.to_string()))
+        .bind(("status", DepositStatus::Submitted))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        if db::violates_index(&error, "deposits_tx_user") {
+            return Err(DepositError::AlreadyClaimed(tx_hash));
+        }
+        return Err(error.into());
+    }
+
+    response
+        .take::<Option<Deposit>>(0)?
+        .ok_or(DepositError::NotRecorded(tx_hash))
+}
+
+pub async fn list_for_user(
+    db: &Surreal<Any>,
+    user_id: &str,
+) -> Result<Vec<Deposit>, surrealdb::Error> {
+    db.query("SELECT * FROM deposits WHERE userId = type::thing($userId) ORDER BY createdAt DESC")
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Vec<Deposit>>(0)
+}
+
+/// The user's deposit in `tx_hash` on `chain_id`. The same hash can be
+/// claimed once on every chain.
+pub async fn get_for_user(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    user_id: &str,
+    tx_hash: &str,
+) -> Result<Option<Deposit>, surrealdb::Error> {
+    db.query(
+        "SELECT * FROM deposits WHERE chainId = $chainId AND userId = type::thing($userId) AND txHash = type::string($txHash)",
+    )
+    .bind(("chainId", chain_id))
+    .bind(("userId", user_id.to_string()))
+    .bind(("txHash", tx_hash.to_lowercase()))
+    .await?
+    .take::<Option<Deposit>>(0)
+}
+
+/// Deposits on `chain_id` the worker still has to look at, oldest first.
+pub async fn pending(db: &Surreal<Any>, chain_id: u64) -> Result<Vec<Deposit>, surrealdb::Error> {
+    db.query(
+        "SELECT * FROM deposits WHERE chainId = $chainId AND status IN $statuses ORDER BY createdAt ASC",
+    )
+    .bind(("chainId", chain_id))
+        .bind((
+            "statuses",
+            vec![DepositStatus::Submitted, DepositStatus::Seen],
+        ))
+        .await?
+        .take::<Vec<Deposit>>(0)
+}
+
+pub async fn mark_seen(
+    db: &Surreal<Any>,
+    deposit: &Deposit,
+    block_number: u64,
+    block_hash: &str,
+    confirmations: u64,
+    amounts: &[TokenAmount],
+) -> Result<(), surrealdb::Error> {
+    mark_mined(
+        db,
+        deposit,
+        DepositStatus::Seen,
+        (block_number, block_hash),
+        confirmations,
+        amounts,
+    )
+    .await
+}
+
+/// Puts a deposit whose block was reorged out back in the queue, to be
+/// confirmed again from scratch once it is mined in the new chain.
+pub async fn requeue(db: &Surreal<Any>, deposit: &Deposit) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            blockNumber = NONE,
+            blockHash = NONE,
+            confirmations = 0,
+            reorgs = (reorgs OR 0) + 1,
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", deposit.id.to_string()))
+    .bind(("status", DepositStatus::Submitted))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+pub async fn mark_confirmed(
+    db: &Surreal<Any>,
+    deposit: &Deposit,
+    block_number: u64,
+    block_hash: &str,
+    confirmations: u64,
+    amounts: &[TokenAmount],
+) -> Result<(), surrealdb::Error> {
+    mark_mined(
+        db,
+        deposit,
+        DepositStatus::Confirmed,
+        (block_number, block_hash),
+        confirmations,
+        amounts,
+    )
+    .await
+}
+
+/// Records the block a deposit was mined in and what it deposited. `token`
+/// and `amount` are only set for a deposit in a single token.
+async fn mark_mined(
+    db: &Surreal<Any>,
+    deposit: &Deposit,
+    status: DepositStatus,
+    (block_number, block_hash): (u64, &str),
+    confirmations: u64,
+    amounts: &[TokenAmount],
+) -> Result<(), surrealdb::Error> {
+    let (token, amount) = match amounts {
+        [only] => (Some(only.token.clone()), Some(only.amount)),
+        _ => (None, None),
+    };
+
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            blockNumber = $blockNumber,
+            blockHash = type::string($blockHash),
+            confirmations = $confirmations,
+            token = $tokenSymbol,
+            amount = $amount,
+            amounts = $amounts,
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", deposit.id.to_string()))
+    .bind(("status", status))
+    .bind(("blockNumber", block_number))
+    .bind(("blockHash", block_hash.to_string()))
+    .bind(("confirmations", confirmations))
+    .bind(("tokenSymbol", token))
+    .bind(("amount", amount))
+    .bind(("amounts", amounts.to_vec()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+pub async fn mark_failed(
+    db: &Surreal<Any>,
+    deposit: &Deposit,
+    error: &str,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            error = type::string($error),
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", deposit.id.to_string()))
+    .bind(("status", DepositStatus::Failed))
+    .bind(("error", error.to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+pub async fn mark_rejected(
+    db: &Surreal<Any>,
+    deposit: &Deposit,
+    error: &str,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            error = type::string($error),
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", deposit.id.to_string()))
+    .bind(("status", DepositStatus::Rejected))
+    .bind(("error", error.to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Fails deposits on `chain_id` whose transaction has not been mined within
+/// `timeout_secs` of being submitted or requeued.
+pub async fn expire_unseen(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    timeout_secs: u64,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE deposits SET
+            status = type::string($failed),
+            error = 'Transaction not found',
+            updatedAt = time::now()
+        WHERE chainId = $chainId
+        AND status = type::string($submitted)
+        AND updatedAt < time::now() - type::duration($timeout);
+        ",
+    )
+    .bind(("chainId", chain_id))
+    .bind(("failed", DepositStatus::Failed))
+    .bind(("submitted", DepositStatus::Submitted))
+    .bind(("timeout", format!("{}s", timeout_secs)))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use alloy::primitives::U256;
+
+    use super::*;
+    use crate::money::models::Amount;
+
+    #[tokio::test]
+    async fn records_what_was_deposited_of_each_token_and_its_dust() {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        let amount = |token: &str, units| TokenAmount {
+            token: token.to_string(),
+            amount: Amount::from_base_units(units),
+            dust: U256::ZERO,
+        };
+
+        let single = create(&db, 1, "user:alice", "0x01").await.unwrap();
+        let usdt = [amount("USDT", 100)];
+        mark_seen(&db, &single, 10, "0xaa", 1, &usdt).await.unwrap();
+        mark_confirmed(&db, &single, 10, "0xaa", 3, &usdt)
+            .await
+            .unwrap();
+
+        // The same hash claimed on another chain is a deposit of its own.
+        create(&db, 2, "user:alice", "0x01").await.unwrap();
+
+        let mixed = create(&db, 1, "user:alice", "0x02").await.unwrap();
+        let dusty = TokenAmount {
+            dust: U256::from(7),
+            ..amount("USDC", 250)
+        };
+        let both = [amount("USDT", 100), dusty];
+        mark_confirmed(&db, &mixed, 11, "0xbb", 3, &both)
+            .await
+            .unwrap();
+
+        let single = get_for_user(&db, 1, "user:alice", "0x01")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(single.status, DepositStatus::Confirmed);
+        assert_eq!(single.token.as_deref(), Some("USDT"));
+        assert_eq!(single.amount, Some(Amount::from_base_units(100)));
+        assert_eq!(single.amounts, usdt);
+
+        let mixed = get_for_user(&db, 1, "user:alice", "0x02")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(mixed.token, None);
+        assert_eq!(mixed.amount, None);
+        assert_eq!(mixed.amounts, both);
+    }
+}
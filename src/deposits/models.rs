@@ -0,0 +1,169 @@
+use alloy::primitives::{Address, U256};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use surrealdb::sql::{Datetime, Thing};
+use thiserror::Error;
+
+use crate::hd_wallet::HdWalletError;
+use crate::money::models::{Amount, MoneyError};
+
+/// Where a submitted deposit is in its confirmation lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepositStatus {
+    /// Recorded, but no receipt has been found yet.
+    Submitted,
+    /// Mined, waiting for enough confirmations.
+    Seen,
+    /// Confirmed and credited to the ledger.
+    Confirmed,
+    /// Reverted, never mined or not a valid deposit.
+    Failed,
+    /// A valid deposit sent from a wallet not linked to the user. Not credited.
+    Rejected,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deposit {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    #[serde(rename = "txHash")]
+    pub tx_hash: String,
+    #[serde(rename = "userId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub user_id: Thing,
+    pub status: DepositStatus,
+    #[serde(rename = "blockNumber")]
+    pub block_number: Option<u64>,
+    #[serde(rename = "blockHash")]
+    pub block_hash: Option<String>,
+    pub confirmations: u64,
+    /// How many times the block the deposit was seen in was reorged out.
+    #[serde(default)]
+    pub reorgs: u32,
+    /// Symbol of the token deposited, known once the receipt is seen. Left
+    /// unset when the transaction deposited more than one token.
+    pub token: Option<String>,
+    pub amount: Option<Amount>,
+    /// How much of each token was deposited, known once the receipt is seen.
+    #[serde(default)]
+    pub amounts: Vec<TokenAmount>,
+    pub error: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Datetime,
+}
+
+/// How much of one token a deposit brought in.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenAmount {
+    /// Symbol of the token.
+    pub token: String,
+    pub amount: Amount,
+    /// Token units below the smallest [`Amount`] that arrived with it but
+    /// were not credited.
+    #[serde_as(as = "DisplayFromStr")]
+    #[serde(default, skip_serializing_if = "U256::is_zero")]
+    pub dust: U256,
+}
+
+/// A Transfer log that pays one of our deposit addresses in a registered
+/// token.
+#[derive(Debug, Clone)]
+pub struct DepositTransfer {
+    pub log_index: u64,
+    pub from: Address,
+    pub to: Address,
+    /// Symbol of the token transferred.
+    pub token: String,
+    /// The part of the transfer that is credited.
+    pub amount: Amount,
+    /// The rest, in the token's smallest unit, too small to be an [`Amount`].
+    pub dust: U256,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepositAddress {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde(rename = "userId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub user_id: Thing,
+    pub index: u32,
+    pub address: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+}
+
+#[derive(Debug, Error)]
+pub enum DepositError {
+    #[error("deposit {0} not found")]
+    NotFound(String),
+
+    #[error("deposit {0} has already been claimed")]
+    AlreadyClaimed(String),
+
+    #[error("deposit {0} has already been credited")]
+    AlreadyCredited(String),
+
+    #[error("transaction {0} reverted")]
+    Reverted(String),
+
+    #[error("transaction {0} has no transfer of the deposit token to the deposit wallet")]
+    NoMatchingTransfer(String),
+
+    #[error("transaction {tx_hash} was sent from {from}, which is not linked to this account")]
+    SenderNotLinked { tx_hash: String, from: Address },
+
+    #[error("amount transferred in {tx_hash} is invalid: {source}")]
+    InvalidAmount { tx_hash: String, source: MoneyError },
+
+    #[error("deposit {0} could not be recorded")]
+    NotRecorded(String),
+
+    #[error("per-user deposit addresses are not configured")]
+    AddressesNotConfigured,
+
+    #[error("deposit address could not be allocated")]
+    AddressNotAllocated,
+
+    #[error(transparent)]
+    HdWallet(#[from] HdWalletError),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<surrealdb::Error> for DepositError {
+    fn from(error: surrealdb::Error) -> Self {
+        DepositError::Surrealdb(Box::new(error))
+    }
+}
+
+impl DepositError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            DepositError::NotFound(_) => StatusCode::NOT_FOUND,
+            DepositError::AlreadyClaimed(_) | DepositError::AlreadyCredited(_) => {
+                StatusCode::CONFLICT
+            }
+            DepositError::Reverted(_)
+            | DepositError::NoMatchingTransfer(_)
+            | DepositError::SenderNotLinked { .. }
+            | DepositError::InvalidAmount { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            DepositError::AddressesNotConfigured => StatusCode::SERVICE_UNAVAILABLE,
+            DepositError::NotRecorded(_)
+            | DepositError::AddressNotAllocated
+            | DepositError::HdWallet(_)
+            | DepositError::Surrealdb(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
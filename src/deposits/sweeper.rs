@@ -0,0 +1,302 @@
+//! Moves the tokens on per-user deposit addresses into the hot wallet, where
+//! withdrawals are paid out from.
+//!
+//! A deposit address holds no ETH to pay for its own transfer, so the hot
+//! wallet first sends it the gas. The top-up is stored before it is sent,
+//! like a withdrawal, and the address is left alone until it is mined. The
+//! sweep itself is signed with the address's derived key and needs no
+//! record: while it is pending the address has a transaction in flight and
+//! is skipped, and if the node drops it the next pass sends it again.
+//!
+//! Sweeping moves funds between two addresses the platform already holds,
+//! so the ledger is not touched.
+//!
+//! One sweeper runs per chain.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::eips::eip1559::Eip1559Estimation;
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, Bytes, TxHash, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol_types::SolCall;
+use serde::Deserialize;
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use super::addresses;
+use super::models::DepositAddress;
+use crate::api::private::IERC20;
+use crate::chain::registry::Chain;
+use crate::hd_wallet::HdWallet;
+use crate::hot_wallet::signer::RawKeySigner;
+use crate::hot_wallet::{HotWallet, SignedTx};
+use crate::money::Token;
+use crate::withdrawals::worker::is_nonce_taken;
+use crate::AppState;
+
+/// Gas the hot wallet sent a deposit address, not known to be mined yet.
+#[derive(Debug, Deserialize)]
+struct TopUp {
+    #[serde(rename = "txHash")]
+    tx_hash: String,
+    #[serde(rename = "rawTx")]
+    raw_tx: String,
+}
+
+/// Sweeps every deposit address on `chain_id` every
+/// `sweep_interval_secs`. Runs for the lifetime of the server under
+/// [`crate::worker::supervise`], once per chain.
+pub async fn run(state: AppState, chain_id: u64) -> anyhow::Result<()> {
+    let Some(hd_wallet) = &state.hd_wallet else {
+        println!("No HD seed configured, not sweeping chain {}", chain_id);
+        return Ok(());
+    };
+    let chain = state.chains.get(chain_id)?;
+    let poll_interval = Duration::from_secs(state.sweep_interval_secs);
+    let tokens: Vec<Token> = state
+        .tokens
+        .enabled()
+        .filter(|token| token.chain_id == chain_id)
+        .map(|token| token.token())
+        .collect();
+
+    // Top-ups take the hot wallet's nonces too, so start from the same one
+    // the withdrawal worker does.
+    chain.hot_wallet.recover(&state.database).await?;
+
+    println!(
+        "Sweeper started for {} on chain {}",
+        chain.hot_wallet.address(),
+        chain_id
+    );
+
+    loop {
+        for deposit_address in addresses::list(&state.database).await? {
+            if let Err(err) = sweep(&state, chain, hd_wallet, &deposit_address, &tokens).await {
+                println!(
+                    "Error sweeping deposit address {}: {:?}",
+                    deposit_address.address, err
+                );
+            }
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Sends the first token on `deposit_address` worth sweeping to the hot
+/// wallet, or the gas to do so. The rest follow on the next passes.
+async fn sweep(
+    state: &AppState,
+    chain: &Chain,
+    hd_wallet: &HdWallet,
+    deposit_address: &DepositAddress,
+    tokens: &[Token],
+) -> anyhow::Result<()> {
+    let db = &state.database;
+    let provider = &chain.provider;
+    let address = Address::from_str(&deposit_address.address)?;
+
+    if !top_up_settled(db, chain, address).await? {
+        return Ok(());
+    }
+
+    let wallet = HotWallet::new(
+        Arc::new(RawKeySigner::new(hd_wallet.signer(deposit_address.index)?)),
+        provider.clone(),
+        chain.id(),
+    );
+    let mut next_nonce = wallet.lock().await;
+    wallet.resync(&mut next_nonce).await?;
+    if *next_nonce > provider.get_transaction_count(address).await? {
+        // The last sweep is still pending.
+        return Ok(());
+    }
+
+    for token in tokens {
+        let balance = balance_of(chain, token, address).await?;
+        if balance.is_zero() || balance < token.to_token_units(state.sweep_min_amount)? {
+            continue;
+        }
+
+        let call = IERC20::transferCall {
+            to: chain.hot_wallet.address(),
+            amount: balance,
+        };
+        let tx = TransactionRequest::default()
+            .with_to(token.address)
+            .with_value(U256::ZERO)
+            .with_call(&call);
+        let fees = estimate_fees(state, chain).await?;
+        let gas_cost =
+            U256::from(wallet.estimate_gas(tx.clone()).await?) * U256::from(fees.max_fee_per_gas);
+        let gas_balance = provider.get_balance(address).await?;
+        if gas_balance < gas_cost {
+            return top_up(db, chain, address, gas_cost - gas_balance, fees).await;
+        }
+
+        let signed = wallet.sign(&next_nonce, tx, *next_nonce, fees).await?;
+        let _ = provider.send_raw_transaction(&signed.raw).await?;
+        *next_nonce = signed.nonce + 1;
+
+        println!(
+            "Swept {} of token {} from {} as {}",
+            balance, token.address, address, signed.hash
+        );
+        return Ok(());
+    }
+
+    Ok(())
+}
+
+async fn balance_of(chain: &Chain, token: &Token, owner: Address) -> anyhow::Result<U256> {
+    let tx = TransactionRequest::default()
+        .with_to(token.address)
+        .with_call(&IERC20::balanceOfCall { owner });
+    let output = chain.provider.call(tx).await?;
+
+    Ok(IERC20::balanceOfCall::abi_decode_returns(&output)?)
+}
+
+/// What the network asks for, capped like a withdrawal's fees.
+async fn estimate_fees(state: &AppState, chain: &Chain) -> anyhow::Result<Eip1559Estimation> {
+    let fees = chain.hot_wallet.estimate_fees().await?;
+    let max_fee_per_gas = fees.max_fee_per_gas.min(state.withdrawal_max_fee_per_gas);
+
+    Ok(Eip1559Estimation {
+        max_fee_per_gas,
+        max_priority_fee_per_gas: fees.max_priority_fee_per_gas.min(max_fee_per_gas),
+    })
+}
+
+/// Sends `value` wei of gas from the hot wallet to `address`.
+async fn top_up(
+    db: &Surreal<Any>,
+    chain: &Chain,
+    address: Address,
+    value: U256,
+    fees: Eip1559Estimation,
+) -> anyhow::Result<()> {
+    let hot_wallet = &chain.hot_wallet;
+    let mut next_nonce = hot_wallet.lock().await;
+    let tx = TransactionRequest::default()
+        .with_to(address)
+        .with_value(value);
+    let signed = hot_wallet.sign(&next_nonce, tx, *next_nonce, fees).await?;
+    save_top_up(db, chain, address, &signed).await?;
+    *next_nonce = signed.nonce + 1;
+
+    println!(
+        "Topping up deposit address {} with {} wei as {}",
+        address, value, signed.hash
+    );
+
+    // If this fails it is sent again on the next pass.
+    if let Err(err) = hot_wallet
+        .provider()
+        .send_raw_transaction(&signed.raw)
+        .await
+    {
+        println!("Broadcasting top-up {} failed: {}", signed.hash, err);
+    }
+
+    Ok(())
+}
+
+/// Whether `address` has no top-up waiting to be mined. A waiting one is
+/// sent again, in case the node dropped it.
+async fn top_up_settled(
+    db: &Surreal<Any>,
+    chain: &Chain,
+    address: Address,
+) -> anyhow::Result<bool> {
+    let Some(top_up) = load_top_up(db, chain.id(), address).await? else {
+        return Ok(true);
+    };
+
+    let provider = chain.hot_wallet.provider();
+    let tx_hash = TxHash::from_str(&top_up.tx_hash)?;
+    if provider.get_transaction_receipt(tx_hash).await?.is_some() {
+        delete_top_up(db, chain.id(), address).await?;
+        return Ok(true);
+    }
+
+    match provider
+        .send_raw_transaction(&Bytes::from_str(&top_up.raw_tx)?)
+        .await
+    {
+        // The nonce went to another transaction, so this one can never be
+        // mined; the next pass tops the address up again if it still needs it.
+        Err(err) if is_nonce_taken(&err.to_string()) => {
+            println!("Top-up {} was replaced: {}", tx_hash, err);
+            delete_top_up(db, chain.id(), address).await?;
+        }
+        Ok(_) | Err(_) => {}
+    }
+
+    Ok(false)
+}
+
+async fn load_top_up(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    address: Address,
+) -> Result<Option<TopUp>, surrealdb::Error> {
+    db.query("SELECT txHash, rawTx FROM ONLY type::thing('top_ups', [$chainId, $address])")
+        .bind(("chainId", chain_id))
+        .bind(("address", address.to_string().to_lowercase()))
+        .await?
+        .take::<Option<TopUp>>(0)
+}
+
+/// Stores a signed top-up together with the hot wallet nonce it used, so a
+/// withdrawal signed after a restart cannot take the same one.
+async fn save_top_up(
+    db: &Surreal<Any>,
+    chain: &Chain,
+    address: Address,
+    signed: &SignedTx,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        BEGIN TRANSACTION;
+        UPSERT type::thing('top_ups', [$chainId, $address]) SET
+            txHash = type::string($txHash),
+            rawTx = type::string($rawTx),
+            nonce = $nonce,
+            createdAt = time::now();
+        UPSERT type::thing('nonces', [$chainId, $hotWallet]) SET
+            next = math::max([next OR 0, $nonce + 1]),
+            updatedAt = time::now();
+        COMMIT TRANSACTION;
+        ",
+    )
+    .bind(("chainId", chain.id()))
+    .bind(("address", address.to_string().to_lowercase()))
+    .bind(("txHash", signed.hash.to_string()))
+    .bind(("rawTx", signed.raw.to_string()))
+    .bind(("nonce", signed.nonce))
+    .bind(("hotWallet", chain.hot_wallet.address().to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+async fn delete_top_up(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    address: Address,
+) -> Result<(), surrealdb::Error> {
+    db.query("DELETE type::thing('top_ups', [$chainId, $address])")
+        .bind(("chainId", chain_id))
+        .bind(("address", address.to_string().to_lowercase()))
+        .await?
+        .check()?;
+
+    Ok(())
+}
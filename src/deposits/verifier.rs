@@ -0,0 +1,78 @@
+use alloy::primitives::Address;
+use alloy::rpc::types::TransactionReceipt;
+
+use super::models::{DepositError, DepositTransfer};
+use crate::api::private::Transfer;
+use crate::tokens::TokenRegistry;
+
+/// Finds every Transfer in `receipt` that moves an enabled token into one of
+/// `recipients`.
+///
+/// Transfers of other tokens, or of a registered token to anyone else, are
+/// ignored; a receipt without a single matching Transfer is not a deposit.
+/// Transfers of several registered tokens are all matched, each to be
+/// credited in its own token.
+/// A Transfer carrying dust too small to be held as an amount is matched for
+/// the part that can, with the dust reported next to it.
+pub fn verify_transfers(
+    receipt: &TransactionReceipt,
+    tokens: &TokenRegistry,
+    chain_id: u64,
+    recipients: &[Address],
+) -> Result<Vec<DepositTransfer>, DepositError> {
+    let tx_hash = receipt.transaction_hash.to_string();
+
+    if !receipt.status() {
+        return Err(DepositError::Reverted(tx_hash));
+    }
+
+    let mut transfers = Vec::new();
+    for log in receipt.logs() {
+        if log.removed {
+            continue;
+        }
+        let Some(token) = tokens.by_address(chain_id, log.address()) else {
+            continue;
+        };
+        let Ok(decoded) = log.log_decode::<Transfer>() else {
+            continue;
+        };
+        let Transfer { from, to, value } = decoded.inner.data;
+        if !recipients.contains(&to) {
+            continue;
+        }
+
+        println!("Matched transfer: {from} -> {to} = {value}");
+
+        let log_index = log
+            .log_index
+            .ok_or_else(|| DepositError::NoMatchingTransfer(tx_hash.clone()))?;
+        let (amount, dust) = token.token().to_amount_and_dust(value).map_err(|source| {
+            DepositError::InvalidAmount {
+                tx_hash: tx_hash.clone(),
+                source,
+            }
+        })?;
+        if !dust.is_zero() {
+            println!(
+                "Transfer {}:{} carries {} units of {} too small to credit",
+                tx_hash, log_index, dust, token.symbol
+            );
+        }
+
+        transfers.push(DepositTransfer {
+            log_index,
+            from,
+            to,
+            token: token.symbol.clone(),
+            amount,
+            dust,
+        });
+    }
+
+    if transfers.is_empty() {
+        return Err(DepositError::NoMatchingTransfer(tx_hash));
+    }
+
+    Ok(transfers)
+}
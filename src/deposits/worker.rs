@@ -0,0 +1,281 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use alloy::primitives::{Address, TxHash};
+use alloy::providers::Provider;
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use super::addresses;
+use super::models::{Deposit, DepositError, DepositTransfer, TokenAmount};
+use super::verifier::verify_transfers;
+use crate::chain::registry::Chain;
+use crate::chain::{self, Confirmation};
+use crate::ledger::models::{Account, EntryKind, JournalEntry, LedgerError};
+use crate::money::models::MoneyError;
+use crate::{ledger, wallets, AppState};
+
+/// Polls every pending deposit on `chain_id` until it is confirmed or
+/// failed. Runs for the lifetime of the server under
+/// [`crate::worker::supervise`], once per chain.
+pub async fn run(state: AppState, chain_id: u64) -> anyhow::Result<()> {
+    let chain = state.chains.get(chain_id)?;
+    let poll_interval = Duration::from_secs(state.deposit_poll_interval_secs);
+    let wallet = Address::from_str(&state.wallet_address)?;
+
+    println!("Deposit worker started on chain {}", chain_id);
+
+    loop {
+        super::expire_unseen(&state.database, chain_id, state.deposit_timeout_secs).await?;
+
+        for deposit in super::pending(&state.database, chain_id).await? {
+            if let Err(err) = process(&state, chain, &deposit, wallet).await {
+                println!("Error processing deposit {}: {:?}", deposit.tx_hash, err);
+            }
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+async fn process(
+    state: &AppState,
+    chain: &Chain,
+    deposit: &Deposit,
+    wallet: Address,
+) -> anyhow::Result<()> {
+    let db = &state.database;
+    // The receipt and its confirmations have to come from the same endpoint.
+    let provider = &chain.rpc.pinned();
+    let tx_hash = TxHash::from_str(&deposit.tx_hash)?;
+
+    let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? else {
+        if deposit.block_hash.is_some() {
+            println!("Deposit {} was reorged out", deposit.tx_hash);
+            super::requeue(db, deposit).await?;
+        } else {
+            println!("Receipt for {} not found yet", deposit.tx_hash);
+        }
+        return Ok(());
+    };
+
+    let user_id = deposit.user_id.to_string();
+    let mut recipients = vec![wallet];
+    if let Some(deposit_address) = addresses::of_user(db, &user_id).await? {
+        recipients.push(Address::from_str(&deposit_address.address)?);
+    }
+
+    let transfers = match verify_transfers(&receipt, &state.tokens, chain.id(), &recipients) {
+        Ok(transfers) => transfers,
+        Err(err) => {
+            println!("Rejecting deposit {}: {}", deposit.tx_hash, err);
+            super::mark_failed(db, deposit, &err.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    // Anything sent to the user's own deposit address is theirs; transfers to
+    // the shared wallet can only be attributed through the sender. A batch
+    // payout can pay other users in the same transaction, and those transfers
+    // are left to their own deposits.
+    let mut theirs = Vec::with_capacity(transfers.len());
+    let mut unlinked = None;
+    for transfer in transfers {
+        if transfer.to != wallet
+            || wallets::is_linked(db, &user_id, &transfer.from.to_string()).await?
+        {
+            theirs.push(transfer);
+        } else {
+            unlinked.get_or_insert(transfer.from);
+        }
+    }
+    let transfers = theirs;
+    if let (true, Some(from)) = (transfers.is_empty(), unlinked) {
+        let err = DepositError::SenderNotLinked {
+            tx_hash: deposit.tx_hash.clone(),
+            from,
+        };
+        println!("Rejecting deposit {}: {}", deposit.tx_hash, err);
+        super::mark_rejected(db, deposit, &err.to_string()).await?;
+        return Ok(());
+    }
+    let amounts = amounts_by_token(&transfers)?;
+
+    let block_number = receipt
+        .block_number
+        .ok_or_else(|| anyhow::anyhow!("No block number found"))?;
+    let block_hash = receipt
+        .block_hash
+        .ok_or_else(|| anyhow::anyhow!("No block hash found"))?;
+
+    if deposit
+        .block_hash
+        .as_ref()
+        .is_some_and(|seen| *seen != block_hash.to_string())
+    {
+        println!("Deposit {} moved to block {}", deposit.tx_hash, block_hash);
+        super::requeue(db, deposit).await?;
+        return Ok(());
+    }
+
+    let required = chain.config.confirmations;
+    println!("Confirming blocks: {}", required);
+    println!("Transaction Block: {}", block_number);
+
+    let confirmations =
+        match chain::confirmation(provider, block_number, block_hash, required).await? {
+            Confirmation::Confirmed { confirmations } => confirmations,
+            Confirmation::Pending { confirmations } => {
+                super::mark_seen(
+                    db,
+                    deposit,
+                    block_number,
+                    &block_hash.to_string(),
+                    confirmations,
+                    &amounts,
+                )
+                .await?;
+                return Ok(());
+            }
+            Confirmation::Reorged => {
+                println!("Deposit {} was reorged out", deposit.tx_hash);
+                super::requeue(db, deposit).await?;
+                return Ok(());
+            }
+        };
+
+    for transfer in &transfers {
+        let reference = credit_reference(deposit.chain_id, &deposit.tx_hash, transfer.log_index);
+        match credit(db, deposit.chain_id, &user_id, transfer, &reference).await {
+            Ok(()) => {}
+            Err(LedgerError::Duplicate { .. }) => {
+                println!("Deposit {} was already credited", reference)
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    super::mark_confirmed(
+        db,
+        deposit,
+        block_number,
+        &block_hash.to_string(),
+        confirmations,
+        &amounts,
+    )
+    .await?;
+
+    let deposited: Vec<String> = amounts
+        .iter()
+        .map(|deposited| format!("{} {}", deposited.amount, deposited.token))
+        .collect();
+    println!(
+        "Deposit {} of {} confirmed!",
+        deposit.tx_hash,
+        deposited.join(" and ")
+    );
+
+    Ok(())
+}
+
+/// Totals `transfers` and their dust per token, in the order each token
+/// first appears.
+fn amounts_by_token(transfers: &[DepositTransfer]) -> Result<Vec<TokenAmount>, MoneyError> {
+    let mut amounts: Vec<TokenAmount> = Vec::new();
+    for transfer in transfers {
+        match amounts
+            .iter_mut()
+            .find(|total| total.token == transfer.token)
+        {
+            Some(total) => {
+                total.amount = total.amount.checked_add(transfer.amount)?;
+                total.dust = total
+                    .dust
+                    .checked_add(transfer.dust)
+                    .ok_or(MoneyError::Overflow)?;
+            }
+            None => amounts.push(TokenAmount {
+                token: transfer.token.clone(),
+                amount: transfer.amount,
+                dust: transfer.dust,
+            }),
+        }
+    }
+
+    Ok(amounts)
+}
+
+/// The journal reference a Transfer log is credited under.
+///
+/// A deposit record is unique per chain, transaction and user, since a batch
+/// payout is a deposit for every user it pays. Crediting is what has to be
+/// unique per Transfer log, and is: the journal's unique `kind, reference`
+/// index takes a deposit credit once per chain id, transaction hash and log
+/// index, however often the log is processed.
+fn credit_reference(chain_id: u64, tx_hash: &str, log_index: u64) -> String {
+    format!("deposit:{}:{}:{}", chain_id, tx_hash, log_index)
+}
+
+/// Credits a confirmed Transfer to the user who claimed the deposit, taking
+/// the tokens into custody on the chain they arrived on. `reference`
+/// identifies the Transfer log, so a log is credited at most once no matter
+/// how often it is processed.
+async fn credit(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    user_id: &str,
+    transfer: &DepositTransfer,
+    reference: &str,
+) -> Result<(), LedgerError> {
+    ledger::post(
+        db,
+        JournalEntry::new(EntryKind::Deposit, &transfer.token, reference).transfer(
+            Account::Custody(chain_id),
+            Account::Available(user_id.to_string()),
+            transfer.amount,
+        ),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U256;
+    use surrealdb::engine::any;
+
+    use super::*;
+    use crate::db;
+    use crate::money::models::Amount;
+
+    #[tokio::test]
+    async fn a_replayed_transfer_log_is_credited_once() {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        let transfer = |log_index| DepositTransfer {
+            log_index,
+            from: Address::repeat_byte(0xee),
+            to: Address::repeat_byte(0x0a),
+            token: "USDT".to_string(),
+            amount: Amount::from_base_units(100),
+            dust: U256::ZERO,
+        };
+
+        let first = credit_reference(1, "0x01", 0);
+        credit(&db, 1, "user:alice", &transfer(0), &first)
+            .await
+            .unwrap();
+        let replayed = credit(&db, 1, "user:alice", &transfer(0), &first).await;
+        assert!(matches!(replayed, Err(LedgerError::Duplicate { .. })));
+
+        // Another log in the same transaction is a credit of its own.
+        let second = credit_reference(1, "0x01", 1);
+        credit(&db, 1, "user:alice", &transfer(1), &second)
+            .await
+            .unwrap();
+
+        let balance = ledger::available_balance(&db, "user:alice", "USDT")
+            .await
+            .unwrap();
+        assert_eq!(balance, Amount::from_base_units(200));
+    }
+}
@@ -0,0 +1,45 @@
+//! A record of what the server changes on its own schedule.
+//!
+//! Every trade the expiry job expires, offer it closes and nonce it purges is
+//! written to the `events` table in the same transaction as the change, so
+//! nothing the job did can go unrecorded.
+
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::{Datetime, Thing};
+use surrealdb::Surreal;
+
+/// What happened to an event's subject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A pending trade was not paid in time.
+    TradeExpired,
+    /// An offer was closed and what was left of its escrow released.
+    OfferClosed,
+    /// A sign-in nonce expired unused.
+    NoncePurged,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    pub kind: EventKind,
+    /// The record the event happened to.
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub subject: Thing,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+}
+
+/// The latest `limit` events, newest first.
+pub async fn latest(db: &Surreal<Any>, limit: u32) -> Result<Vec<Event>, surrealdb::Error> {
+    db.query("SELECT * FROM events ORDER BY createdAt DESC LIMIT $limit")
+        .bind(("limit", limit))
+        .await?
+        .take::<Vec<Event>>(0)
+}
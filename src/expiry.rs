@@ -0,0 +1,164 @@
+//! The scheduled job that moves time-bound records along: trades nobody paid
+//! for in time, offers that were stopped and have nothing open on them
+//! anymore, escrow still held by closed offers, and sign-in nonces nobody
+//! used. Request handlers only read these records, so their state advances
+//! on this schedule and not whenever someone happens to call an endpoint.
+//! Every change the job makes is recorded as an event; see
+//! [`crate::events`].
+
+use std::time::Duration;
+
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use crate::api::auth;
+use crate::{offers, trades, AppState};
+
+/// Runs the expiry job every expiry interval. Runs for the lifetime of the
+/// server under [`crate::worker::supervise`].
+pub async fn run(state: AppState) -> anyhow::Result<()> {
+    let interval = Duration::from_secs(state.expiry_interval_secs);
+
+    println!("Expiry worker started");
+
+    loop {
+        expire(&state.database).await?;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn expire(db: &Surreal<Any>) -> anyhow::Result<()> {
+    // Trades go first, so an offer whose last trade just expired is closed
+    // in the same run.
+    for trade_id in trades::expire_overdue(db).await? {
+        println!("Trade {} expired", trade_id);
+    }
+
+    for offer_id in offers::close_stopped(db).await? {
+        println!("Offer {} closed", offer_id);
+    }
+    for offer_id in offers::release_closed(db).await? {
+        println!("Escrow of offer {} released", offer_id);
+    }
+
+    let purged = auth::purge_expired_nonces(db).await?;
+    if purged > 0 {
+        println!("Purged {} expired nonces", purged);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use super::*;
+    use crate::api::auth::models::{Claims, Role};
+    use crate::api::private::models::{CreateOfferRequest, CreateTransactionRequest};
+    use crate::events::EventKind;
+    use crate::ledger::models::{Account, EntryKind, JournalEntry};
+    use crate::money::models::Amount;
+    use crate::{db, ledger};
+
+    async fn memory_db() -> Surreal<Any> {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn every_change_is_recorded_as_an_event() {
+        let db = memory_db().await;
+        let maker = "user:maker";
+        ledger::post(
+            &db,
+            JournalEntry::new(EntryKind::Opening, "USDT", "opening:user:maker").transfer(
+                Account::Custody(1),
+                Account::Available(maker.to_string()),
+                Amount::from_base_units(1_000),
+            ),
+        )
+        .await
+        .unwrap();
+        let offer_id = offers::create(
+            &db,
+            maker,
+            CreateOfferRequest {
+                offer_type: "sell".to_string(),
+                amount: Amount::from_base_units(1_000),
+                fee: Amount::ZERO,
+                crypto_type: "USDT".to_string(),
+                currency: "EUR".to_string(),
+                price_per_unit: 1,
+                value: 1_000,
+                rev_tag: "maker".to_string(),
+            },
+        )
+        .await
+        .unwrap()
+        .to_string();
+        let trade = trades::open(
+            &db,
+            "user:taker",
+            CreateTransactionRequest {
+                offer_id: offer_id.clone(),
+                amount: Amount::from_base_units(400),
+                random_title: "trade".to_string(),
+            },
+            0,
+        )
+        .await
+        .unwrap();
+        db.query(
+            "
+            UPDATE type::thing($tradeId) SET expiresAt = time::now() - 1m;
+            UPDATE type::thing($offerId) SET status = 'stopped';
+            CREATE nonce SET value = 'unused', exp = time::now() - 1m;
+            ",
+        )
+        .bind(("tradeId", trade.id.to_string()))
+        .bind(("offerId", offer_id.clone()))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+
+        // Overdue trades no longer hold their part of the offer, even before
+        // the job expires them.
+        let terms = offers::terms(&db, &offer_id).await.unwrap();
+        assert_eq!(terms.remaining, Amount::from_base_units(1_000));
+        let taker = Claims {
+            exp: 0,
+            sub: "user:taker".to_string(),
+            role: Role::User,
+        };
+        assert!(matches!(
+            trades::mark_payment_sent(&db, &trade, &taker).await,
+            Err(trades::models::TradeError::Overdue(_))
+        ));
+
+        expire(&db).await.unwrap();
+
+        let mut kinds: Vec<String> = crate::events::latest(&db, 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|event| format!("{:?} {}", event.kind, event.subject.tb))
+            .collect();
+        kinds.sort();
+        assert_eq!(
+            kinds,
+            [
+                format!("{:?} nonce", EventKind::NoncePurged),
+                format!("{:?} offers", EventKind::OfferClosed),
+                format!("{:?} transactions", EventKind::TradeExpired),
+            ]
+        );
+        assert_eq!(
+            ledger::available_balance(&db, maker, "USDT").await.unwrap(),
+            Amount::from_base_units(1_000)
+        );
+    }
+}
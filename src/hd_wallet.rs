@@ -0,0 +1,209 @@
+//! BIP-32 derivation of per-user deposit addresses from a single HD seed.
+//!
+//! Addresses are derived on the BIP-44 Ethereum path `m/44'/60'/0'/0/{index}`,
+//! so the same seed can be loaded into any standard wallet to recover them.
+
+use std::fmt;
+
+use alloy::primitives::Address;
+use alloy::signers::k256::ecdsa::SigningKey;
+use alloy::signers::k256::elliptic_curve::PrimeField;
+use alloy::signers::k256::Scalar;
+use alloy::signers::local::PrivateKeySigner;
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+use thiserror::Error;
+
+const HARDENED: u32 = 1 << 31;
+
+/// `m/44'/60'/0'/0`, the external chain of the first Ethereum account.
+const DEPOSIT_CHAIN: [u32; 4] = [44 | HARDENED, 60 | HARDENED, HARDENED, 0];
+
+#[derive(Debug, Error)]
+pub enum HdWalletError {
+    #[error("HD seed must be 16 to 64 bytes of hex")]
+    InvalidSeed,
+
+    #[error("derived key at index {0} is invalid")]
+    InvalidKey(u32),
+
+    #[error("deposit address index {0} is out of range")]
+    IndexOutOfRange(u32),
+}
+
+#[derive(Clone)]
+struct ExtendedKey {
+    key: SigningKey,
+    chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    fn master(seed: &[u8]) -> Result<Self, HdWalletError> {
+        let (key, chain_code) = hmac_sha512(b"Bitcoin seed", &[seed]);
+        let key = SigningKey::from_bytes(&key.into()).map_err(|_| HdWalletError::InvalidSeed)?;
+
+        Ok(ExtendedKey { key, chain_code })
+    }
+
+    fn child(&self, index: u32) -> Result<Self, HdWalletError> {
+        let index_bytes = index.to_be_bytes();
+        let (tweak, chain_code) = if index >= HARDENED {
+            hmac_sha512(
+                &self.chain_code,
+                &[&[0], &self.key.to_bytes(), &index_bytes],
+            )
+        } else {
+            let public_key = self.key.verifying_key().to_encoded_point(true);
+            hmac_sha512(&self.chain_code, &[public_key.as_bytes(), &index_bytes])
+        };
+
+        let tweak = Option::<Scalar>::from(Scalar::from_repr(tweak.into()))
+            .ok_or(HdWalletError::InvalidKey(index))?;
+        let child = tweak + self.key.as_nonzero_scalar().as_ref();
+        let key = SigningKey::from_bytes(&child.to_bytes())
+            .map_err(|_| HdWalletError::InvalidKey(index))?;
+
+        Ok(ExtendedKey { key, chain_code })
+    }
+}
+
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in data {
+        mac.update(part);
+    }
+    let output = mac.finalize().into_bytes();
+
+    let mut left = [0; 32];
+    let mut right = [0; 32];
+    left.copy_from_slice(&output[..32]);
+    right.copy_from_slice(&output[32..]);
+    (left, right)
+}
+
+/// The deposit chain of an HD wallet. Only the derived keys are kept in memory,
+/// never the seed itself.
+#[derive(Clone)]
+pub struct HdWallet {
+    deposit_chain: ExtendedKey,
+}
+
+impl HdWallet {
+    pub fn from_seed_hex(seed: &str) -> Result<Self, HdWalletError> {
+        let seed = alloy::hex::decode(seed).map_err(|_| HdWalletError::InvalidSeed)?;
+        if !(16..=64).contains(&seed.len()) {
+            return Err(HdWalletError::InvalidSeed);
+        }
+
+        let mut key = ExtendedKey::master(&seed)?;
+        for index in DEPOSIT_CHAIN {
+            key = key.child(index)?;
+        }
+
+        Ok(HdWallet { deposit_chain: key })
+    }
+
+    /// The key controlling the deposit address at `index`.
+    pub fn signer(&self, index: u32) -> Result<PrivateKeySigner, HdWalletError> {
+        if index >= HARDENED {
+            return Err(HdWalletError::IndexOutOfRange(index));
+        }
+
+        let child = self.deposit_chain.child(index)?;
+        Ok(PrivateKeySigner::from_signing_key(child.key))
+    }
+
+    pub fn address(&self, index: u32) -> Result<Address, HdWalletError> {
+        Ok(self.signer(index)?.address())
+    }
+}
+
+impl fmt::Debug for HdWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HdWallet").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::hex;
+    use sha2::Sha512;
+
+    use super::*;
+
+    /// BIP-32 test vector 1: the private key and chain code at each step of
+    /// `m/0'/1/2'/2/1000000000`.
+    #[test]
+    fn derives_bip32_test_vector_1() {
+        let steps = [
+            (
+                None,
+                "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35",
+                "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508",
+            ),
+            (
+                Some(HARDENED),
+                "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+                "47fdacbd0f1097043b78c63c20c34ef4ed9a111d980047ad16282c7ae6236141",
+            ),
+            (
+                Some(1),
+                "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368",
+                "2a7857631386ba23dacac34180dd1983734e444fdbf774041578e9b6adb37c19",
+            ),
+            (
+                Some(2 | HARDENED),
+                "cbce0d719ecf7431d88e6a89fa1483e02e35092af60c042b1df2ff59fa424dca",
+                "04466b9cc8e161e966409ca52986c584f07e9dc81f735db683c3ff6ec7b1503f",
+            ),
+            (
+                Some(2),
+                "0f479245fb19a38a1954c5c7c0ebab2f9bdfd96a17563ef28a6a4b1a2a764ef4",
+                "cfb71883f01676f587d023cc53a35bc7f88f724b1f8c2892ac1275ac822a3edd",
+            ),
+            (
+                Some(1_000_000_000),
+                "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8",
+                "c783e67b921d2beb8f6b389cc646d7263b4145701dadd2161548a8b078e65e9e",
+            ),
+        ];
+
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let mut key = ExtendedKey::master(&seed).unwrap();
+        for (index, private_key, chain_code) in steps {
+            if let Some(index) = index {
+                key = key.child(index).unwrap();
+            }
+            assert_eq!(hex::encode(key.key.to_bytes()), private_key);
+            assert_eq!(hex::encode(key.chain_code), chain_code);
+        }
+    }
+
+    /// The well-known development mnemonic "test test ... junk" gives these
+    /// addresses on `m/44'/60'/0'/0/{index}` in every Ethereum wallet.
+    #[test]
+    fn derives_bip44_ethereum_addresses() {
+        let mnemonic = "test test test test test test test test test test test junk";
+        let mut seed = [0; 64];
+        pbkdf2::pbkdf2_hmac::<Sha512>(mnemonic.as_bytes(), b"mnemonic", 2048, &mut seed);
+
+        let wallet = HdWallet::from_seed_hex(&hex::encode(seed)).unwrap();
+
+        assert_eq!(
+            wallet.address(0).unwrap(),
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert_eq!(
+            wallet.address(1).unwrap(),
+            "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert!(matches!(
+            wallet.address(HARDENED),
+            Err(HdWalletError::IndexOutOfRange(_))
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+//! Decryption of Web3 Secret Storage (V3) keystore files, the format geth,
+//! Foundry's `cast wallet` and most wallets export keys in.
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use alloy::hex;
+use alloy::primitives::keccak256;
+use alloy::signers::k256::ecdsa::SigningKey;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use super::signer::SignerError;
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+#[derive(Deserialize)]
+struct Keystore {
+    #[serde(alias = "Crypto")]
+    crypto: Crypto,
+}
+
+#[derive(Deserialize)]
+struct Crypto {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: String,
+    #[serde(flatten)]
+    kdf: Kdf,
+    mac: String,
+}
+
+#[derive(Deserialize)]
+struct CipherParams {
+    iv: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kdf", content = "kdfparams", rename_all = "lowercase")]
+enum Kdf {
+    Scrypt {
+        dklen: usize,
+        n: u64,
+        r: u32,
+        p: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        dklen: usize,
+        c: u32,
+        prf: String,
+        salt: String,
+    },
+}
+
+fn keystore_error(message: impl Into<String>) -> SignerError {
+    SignerError::Keystore(message.into())
+}
+
+/// Decrypts the private key in a keystore file with `password`.
+pub fn decrypt(json: &[u8], password: &[u8]) -> Result<SigningKey, SignerError> {
+    let keystore: Keystore =
+        serde_json::from_slice(json).map_err(|err| keystore_error(err.to_string()))?;
+    let crypto = keystore.crypto;
+
+    if crypto.cipher != "aes-128-ctr" {
+        return Err(keystore_error(format!(
+            "unsupported cipher {}",
+            crypto.cipher
+        )));
+    }
+
+    let derived_key = derive_key(&crypto.kdf, password)?;
+    if derived_key.len() < 32 {
+        return Err(keystore_error("derived key is too short"));
+    }
+
+    let ciphertext =
+        hex::decode(&crypto.ciphertext).map_err(|_| keystore_error("bad ciphertext"))?;
+    let mac = hex::decode(&crypto.mac).map_err(|_| keystore_error("bad mac"))?;
+    let iv = hex::decode(&crypto.cipherparams.iv).map_err(|_| keystore_error("bad iv"))?;
+
+    let expected_mac = keccak256([&derived_key[16..32], &ciphertext].concat());
+    if expected_mac.as_slice() != mac.as_slice() {
+        return Err(keystore_error("wrong password"));
+    }
+
+    let mut key = ciphertext;
+    Aes128Ctr::new_from_slices(&derived_key[..16], &iv)
+        .map_err(|_| keystore_error("bad iv"))?
+        .apply_keystream(&mut key);
+
+    SigningKey::from_slice(&key).map_err(|_| SignerError::InvalidKey)
+}
+
+fn derive_key(kdf: &Kdf, password: &[u8]) -> Result<Vec<u8>, SignerError> {
+    match kdf {
+        Kdf::Scrypt {
+            dklen,
+            n,
+            r,
+            p,
+            salt,
+        } => {
+            if !n.is_power_of_two() {
+                return Err(keystore_error("scrypt n must be a power of two"));
+            }
+            let salt = hex::decode(salt).map_err(|_| keystore_error("bad salt"))?;
+            let params = scrypt::Params::new(n.trailing_zeros() as u8, *r, *p)
+                .map_err(|err| keystore_error(err.to_string()))?;
+
+            let mut derived_key = vec![0; *dklen];
+            scrypt::scrypt(password, &salt, &params, &mut derived_key)
+                .map_err(|err| keystore_error(err.to_string()))?;
+            Ok(derived_key)
+        }
+        Kdf::Pbkdf2 {
+            dklen,
+            c,
+            prf,
+            salt,
+        } => {
+            if prf != "hmac-sha256" {
+                return Err(keystore_error(format!("unsupported prf {}", prf)));
+            }
+            let salt = hex::decode(salt).map_err(|_| keystore_error("bad salt"))?;
+
+            let mut derived_key = vec![0; *dklen];
+            pbkdf2::pbkdf2_hmac::<Sha256>(password, &salt, *c, &mut derived_key);
+            Ok(derived_key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The test vectors from the Web3 Secret Storage Definition, both of which
+    // hold this key encrypted with the password "testpassword".
+    const PRIVATE_KEY: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+
+    const PBKDF2_KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    const SCRYPT_KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "83dbcc02d8ccb40e466191a123791e0e" },
+            "ciphertext": "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 262144,
+                "p": 8,
+                "r": 1,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    #[test]
+    fn decrypts_the_pbkdf2_test_vector() {
+        let key = decrypt(PBKDF2_KEYSTORE.as_bytes(), b"testpassword").unwrap();
+
+        assert_eq!(hex::encode(key.to_bytes()), PRIVATE_KEY);
+    }
+
+    #[test]
+    fn decrypts_the_scrypt_test_vector() {
+        let key = decrypt(SCRYPT_KEYSTORE.as_bytes(), b"testpassword").unwrap();
+
+        assert_eq!(hex::encode(key.to_bytes()), PRIVATE_KEY);
+    }
+
+    #[test]
+    fn rejects_a_wrong_password() {
+        assert!(matches!(
+            decrypt(PBKDF2_KEYSTORE.as_bytes(), b"wrongpassword"),
+            Err(SignerError::Keystore(message)) if message == "wrong password"
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+//! The platform wallet withdrawals are sent from.
+//!
+//! There is one [`HotWallet`] per chain for the lifetime of the server, all
+//! signing through the same [`WithdrawalSigner`]. Each hands out nonces one
+//! at a time: whoever holds the [`NonceLock`] is the only one signing or
+//! sending on that chain, so two withdrawals can never be signed with the
+//! same nonce. The next nonce is persisted in the `nonces` table, per chain
+//! and address, together with the withdrawal that used it, and recovered
+//! from there and from the node on startup.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use alloy::consensus::TypedTransaction;
+use alloy::eips::eip1559::Eip1559Estimation;
+use alloy::eips::Encodable2718;
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, Bytes, TxHash};
+use alloy::providers::{DynProvider, Provider};
+use alloy::rpc::types::TransactionRequest;
+use alloy::transports::TransportError;
+use serde::Deserialize;
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+use thiserror::Error;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::args::{Args, SignerKind};
+use signer::{KeystoreSigner, RawKeySigner, RemoteSigner, SignerError, WithdrawalSigner};
+
+mod keystore;
+pub mod signer;
+
+#[derive(Debug, Error)]
+pub enum HotWalletError {
+    #[error(transparent)]
+    Signer(#[from] SignerError),
+
+    #[error("transaction could not be built: {0}")]
+    InvalidTransaction(String),
+
+    #[error(transparent)]
+    Rpc(#[from] Box<TransportError>),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<TransportError> for HotWalletError {
+    fn from(error: TransportError) -> Self {
+        HotWalletError::Rpc(Box::new(error))
+    }
+}
+
+impl From<surrealdb::Error> for HotWalletError {
+    fn from(error: surrealdb::Error) -> Self {
+        HotWalletError::Surrealdb(Box::new(error))
+    }
+}
+
+/// A transaction signed by the hot wallet, ready to be broadcast.
+#[derive(Debug, Clone)]
+pub struct SignedTx {
+    pub hash: TxHash,
+    pub raw: Bytes,
+    pub nonce: u64,
+    pub fees: Eip1559Estimation,
+}
+
+#[derive(Debug, Deserialize)]
+struct NonceRecord {
+    next: u64,
+}
+
+/// Exclusive access to the hot wallet's next nonce. Dereferences to it.
+pub type NonceLock<'a> = MutexGuard<'a, u64>;
+
+#[derive(Clone)]
+pub struct HotWallet {
+    signer: Arc<dyn WithdrawalSigner>,
+    provider: DynProvider,
+    chain_id: u64,
+    next_nonce: Arc<Mutex<u64>>,
+}
+
+impl HotWallet {
+    pub fn new(signer: Arc<dyn WithdrawalSigner>, provider: DynProvider, chain_id: u64) -> Self {
+        HotWallet {
+            signer,
+            provider,
+            chain_id,
+            next_nonce: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Sets up the signer chosen in `args`.
+    pub fn signer_from_args(args: &Args) -> Result<Arc<dyn WithdrawalSigner>, SignerError> {
+        Ok(match args.withdrawal_signer {
+            SignerKind::RawKey => {
+                let private_key = args
+                    .private_key
+                    .as_deref()
+                    .ok_or(SignerError::MissingConfig("--private-key"))?;
+                Arc::new(RawKeySigner::from_hex(private_key)?)
+            }
+            SignerKind::Keystore => {
+                let path = args
+                    .keystore_path
+                    .as_deref()
+                    .ok_or(SignerError::MissingConfig("--keystore-path"))?;
+                let password = std::env::var("KEYSTORE_PASSWORD")
+                    .map_err(|_| SignerError::MissingConfig("KEYSTORE_PASSWORD"))?;
+                Arc::new(KeystoreSigner::open(path, &password)?)
+            }
+            SignerKind::Remote => {
+                let url = args
+                    .remote_signer_url
+                    .as_deref()
+                    .ok_or(SignerError::MissingConfig("--remote-signer-url"))?;
+                let address = args
+                    .remote_signer_address
+                    .as_deref()
+                    .and_then(|address| Address::from_str(address).ok())
+                    .ok_or(SignerError::MissingConfig("--remote-signer-address"))?;
+                Arc::new(RemoteSigner::new(url, address)?)
+            }
+        })
+    }
+
+    pub fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    pub fn provider(&self) -> &DynProvider {
+        &self.provider
+    }
+
+    /// Picks up where the last run left off: the next nonce is whichever is
+    /// higher of the persisted one and the node's pending transaction count,
+    /// so transactions signed but never broadcast keep their nonces.
+    pub async fn recover(&self, db: &Surreal<Any>) -> Result<u64, HotWalletError> {
+        let mut next_nonce = self.lock().await;
+        let persisted = persisted_nonce(db, self.chain_id, self.address()).await?;
+        *next_nonce = persisted.max(self.pending_nonce().await?);
+
+        println!(
+            "Hot wallet {} resumes at nonce {} on chain {} (persisted {})",
+            self.address(),
+            *next_nonce,
+            self.chain_id,
+            persisted
+        );
+
+        Ok(*next_nonce)
+    }
+
+    /// Waits until nobody else is signing or sending.
+    pub async fn lock(&self) -> NonceLock<'_> {
+        self.next_nonce.lock().await
+    }
+
+    /// Moves the next nonce up to the node's pending transaction count, for
+    /// when a send was rejected because its nonce was already taken.
+    pub async fn resync(&self, next_nonce: &mut NonceLock<'_>) -> Result<(), HotWalletError> {
+        let pending = self.pending_nonce().await?;
+        if pending > **next_nonce {
+            println!(
+                "Hot wallet nonce moved from {} to {}",
+                **next_nonce, pending
+            );
+            **next_nonce = pending;
+        }
+
+        Ok(())
+    }
+
+    /// Fees the network currently asks for.
+    pub async fn estimate_fees(&self) -> Result<Eip1559Estimation, HotWalletError> {
+        Ok(self.provider.estimate_eip1559_fees().await?)
+    }
+
+    /// Gas `tx` would use if the hot wallet sent it now.
+    pub async fn estimate_gas(&self, tx: TransactionRequest) -> Result<u64, HotWalletError> {
+        let tx = tx.with_from(self.address()).with_chain_id(self.chain_id);
+        Ok(self.provider.estimate_gas(tx).await?)
+    }
+
+    /// Signs `tx` with `nonce` and `fees`. Taking the lock means nothing else
+    /// is signed in the meantime; the next nonce is only advanced once the
+    /// signed transaction is stored.
+    pub async fn sign(
+        &self,
+        _lock: &NonceLock<'_>,
+        tx: TransactionRequest,
+        nonce: u64,
+        fees: Eip1559Estimation,
+    ) -> Result<SignedTx, HotWalletError> {
+        let tx = tx.with_from(self.address()).with_chain_id(self.chain_id);
+        let gas_limit = self.provider.estimate_gas(tx.clone()).await?;
+
+        let tx = tx
+            .with_gas_limit(gas_limit)
+            .with_nonce(nonce)
+            .with_max_fee_per_gas(fees.max_fee_per_gas)
+            .with_max_priority_fee_per_gas(fees.max_priority_fee_per_gas);
+        let Ok(TypedTransaction::Eip1559(tx)) = tx.build_typed_tx() else {
+            return Err(HotWalletError::InvalidTransaction(
+                "not an EIP-1559 transaction".to_string(),
+            ));
+        };
+
+        let envelope = self.signer.sign_transaction(tx).await?;
+
+        Ok(SignedTx {
+            hash: *envelope.tx_hash(),
+            raw: Bytes::from(envelope.encoded_2718()),
+            nonce,
+            fees,
+        })
+    }
+
+    async fn pending_nonce(&self) -> Result<u64, HotWalletError> {
+        Ok(self
+            .provider
+            .get_transaction_count(self.address())
+            .pending()
+            .await?)
+    }
+}
+
+impl fmt::Debug for HotWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HotWallet")
+            .field("address", &self.address())
+            .finish_non_exhaustive()
+    }
+}
+
+async fn persisted_nonce(
+    db: &Surreal<Any>,
+    chain_id: u64,
+    address: Address,
+) -> Result<u64, surrealdb::Error> {
+    let record = db
+        .query("SELECT next FROM ONLY type::thing('nonces', [$chainId, $address])")
+        .bind(("chainId", chain_id))
+        .bind(("address", address.to_string()))
+        .await?
+        .take::<Option<NonceRecord>>(0)?;
+
+    Ok(record.map(|record| record.next).unwrap_or_default())
+}
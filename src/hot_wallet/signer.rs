@@ -0,0 +1,183 @@
+//! Where the hot wallet's signatures come from.
+//!
+//! The key can be a raw hex key (for development), an encrypted keystore
+//! file whose password is read from `KEYSTORE_PASSWORD`, or a remote signer
+//! that keeps the key out of this process entirely. Which one is used is
+//! chosen with `--withdrawal-signer`.
+
+use std::path::Path;
+
+use alloy::consensus::{SignableTransaction, TxEip1559, TxEnvelope};
+use alloy::eips::Decodable2718;
+use alloy::hex::FromHex;
+use alloy::primitives::{Address, Bytes};
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use alloy::rpc::types::TransactionRequest;
+use alloy::signers::k256::ecdsa::SigningKey;
+use alloy::signers::local::PrivateKeySigner;
+use alloy::signers::SignerSync;
+use alloy::transports::TransportError;
+use async_trait::async_trait;
+use thiserror::Error;
+
+use super::keystore;
+
+#[derive(Debug, Error)]
+pub enum SignerError {
+    #[error("hot wallet private key is invalid")]
+    InvalidKey,
+
+    #[error("{0} is required by the configured withdrawal signer")]
+    MissingConfig(&'static str),
+
+    #[error("keystore could not be decrypted: {0}")]
+    Keystore(String),
+
+    #[error("signing failed: {0}")]
+    Signing(String),
+
+    #[error("remote signer returned a transaction that does not match the request")]
+    RemoteMismatch,
+
+    #[error(transparent)]
+    Rpc(#[from] Box<TransportError>),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<TransportError> for SignerError {
+    fn from(error: TransportError) -> Self {
+        SignerError::Rpc(Box::new(error))
+    }
+}
+
+/// Signs the hot wallet's transactions.
+#[async_trait]
+pub trait WithdrawalSigner: Send + Sync {
+    /// The address transactions are sent from.
+    fn address(&self) -> Address;
+
+    async fn sign_transaction(&self, tx: TxEip1559) -> Result<TxEnvelope, SignerError>;
+}
+
+fn sign_locally(signer: &PrivateKeySigner, tx: TxEip1559) -> Result<TxEnvelope, SignerError> {
+    let signature = signer
+        .sign_hash_sync(&tx.signature_hash())
+        .map_err(|err| SignerError::Signing(err.to_string()))?;
+
+    Ok(TxEnvelope::Eip1559(tx.into_signed(signature)))
+}
+
+/// A key passed in as hex. Convenient for development, but the key ends up
+/// in the process arguments or environment. Also signs for the deposit
+/// addresses derived from the HD wallet.
+pub struct RawKeySigner {
+    signer: PrivateKeySigner,
+}
+
+impl RawKeySigner {
+    pub fn new(signer: PrivateKeySigner) -> Self {
+        RawKeySigner { signer }
+    }
+
+    pub fn from_hex(private_key: &str) -> Result<Self, SignerError> {
+        let key_bytes = <[u8; 32]>::from_hex(private_key).map_err(|_| SignerError::InvalidKey)?;
+        let key = SigningKey::from_slice(&key_bytes).map_err(|_| SignerError::InvalidKey)?;
+
+        Ok(RawKeySigner {
+            signer: PrivateKeySigner::from_signing_key(key),
+        })
+    }
+}
+
+#[async_trait]
+impl WithdrawalSigner for RawKeySigner {
+    fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    async fn sign_transaction(&self, tx: TxEip1559) -> Result<TxEnvelope, SignerError> {
+        sign_locally(&self.signer, tx)
+    }
+}
+
+/// A key decrypted from a Web3 Secret Storage (V3) keystore file at startup.
+pub struct KeystoreSigner {
+    signer: PrivateKeySigner,
+}
+
+impl KeystoreSigner {
+    pub fn open(path: &Path, password: &str) -> Result<Self, SignerError> {
+        let key = keystore::decrypt(&std::fs::read(path)?, password.as_bytes())?;
+
+        Ok(KeystoreSigner {
+            signer: PrivateKeySigner::from_signing_key(key),
+        })
+    }
+}
+
+#[async_trait]
+impl WithdrawalSigner for KeystoreSigner {
+    fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    async fn sign_transaction(&self, tx: TxEip1559) -> Result<TxEnvelope, SignerError> {
+        sign_locally(&self.signer, tx)
+    }
+}
+
+/// A signer running elsewhere, reached over JSON-RPC. It is sent the
+/// transaction with `eth_signTransaction` and returns it signed and
+/// RLP-encoded, like Clef or Web3Signer do.
+pub struct RemoteSigner {
+    address: Address,
+    provider: DynProvider,
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str, address: Address) -> Result<Self, SignerError> {
+        let url = url
+            .parse()
+            .map_err(|_| SignerError::MissingConfig("a valid --remote-signer-url"))?;
+
+        Ok(RemoteSigner {
+            address,
+            provider: ProviderBuilder::new().on_http(url).erased(),
+        })
+    }
+}
+
+#[async_trait]
+impl WithdrawalSigner for RemoteSigner {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_transaction(&self, tx: TxEip1559) -> Result<TxEnvelope, SignerError> {
+        let mut request = TransactionRequest::from_transaction(tx.clone());
+        request.from = Some(self.address);
+
+        let raw: Bytes = self
+            .provider
+            .raw_request("eth_signTransaction".into(), (request,))
+            .await?;
+        let envelope = TxEnvelope::decode_2718(&mut raw.as_ref())
+            .map_err(|err| SignerError::Signing(err.to_string()))?;
+
+        // Only send what we asked for, signed by the key we expect.
+        let TxEnvelope::Eip1559(signed) = &envelope else {
+            return Err(SignerError::RemoteMismatch);
+        };
+        let signer = signed
+            .signature()
+            .recover_address_from_prehash(&signed.signature_hash())
+            .map_err(|err| SignerError::Signing(err.to_string()))?;
+        if *signed.tx() != tx || signer != self.address {
+            return Err(SignerError::RemoteMismatch);
+        }
+
+        Ok(envelope)
+    }
+}
@@ -0,0 +1,398 @@
+//! Append-only double-entry ledger.
+//!
+//! Every balance change is a journal entry made of debit and credit legs that
+//! sum to zero. Nothing is ever updated in place: a user's balance is derived
+//! by summing the legs posted to their accounts.
+//!
+//! Custody is kept per chain, since tokens held on one chain cannot pay out
+//! a withdrawal on another.
+
+use models::{Account, EntryKind, JournalEntry, LedgerEntry, LedgerError, LegRecord};
+use surrealdb::engine::any::Any;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+use crate::db;
+use crate::money::models::Amount;
+
+pub mod models;
+
+/// Posts a journal entry and all of its legs in a single transaction. An
+/// entry is posted at most once per kind and reference, so retrying a post
+/// that already went through fails with [`LedgerError::Duplicate`].
+///
+/// User accounts can never go negative: the balance of every user account
+/// the entry debits is checked inside the same transaction, and the entry is
+/// rejected with [`LedgerError::InsufficientFunds`] if it would overdraw one.
+/// Likewise custody on a chain can never go negative, so nothing is paid out
+/// on a chain beyond what was deposited there; an entry that would is
+/// rejected with [`LedgerError::InsufficientCustody`]. Opening entries are
+/// exempt from both checks, since they carry over whatever the legacy balance
+/// was.
+pub async fn post(db: &Surreal<Any>, entry: JournalEntry) -> Result<(), LedgerError> {
+    if entry.legs.iter().any(|leg| leg.debit < 0 || leg.credit < 0) {
+        return Err(LedgerError::NegativeLeg(entry.reference));
+    }
+
+    let debits = Amount::checked_sum(
+        entry
+            .legs
+            .iter()
+            .map(|leg| Amount::from_base_units(leg.debit)),
+    )?;
+    let credits = Amount::checked_sum(
+        entry
+            .legs
+            .iter()
+            .map(|leg| Amount::from_base_units(leg.credit)),
+    )?;
+    if debits != credits {
+        return Err(LedgerError::Unbalanced {
+            reference: entry.reference,
+            debits: debits.base_units(),
+            credits: credits.base_units(),
+        });
+    }
+
+    println!(
+        "Posting {:?} journal entry {} for {} {}",
+        entry.kind, entry.reference, debits, entry.token
+    );
+
+    let legs: Vec<LegRecord> = entry.legs.iter().map(LegRecord::from).collect();
+    let mut debited: Vec<LegRecord> = Vec::new();
+    let guarded = legs
+        .iter()
+        .filter(|leg| entry.kind != EntryKind::Opening && leg.debit > 0 && leg.user_id.is_some());
+    for leg in guarded {
+        if !debited
+            .iter()
+            .any(|seen| seen.account == leg.account && seen.user_id == leg.user_id)
+        {
+            debited.push(leg.clone());
+        }
+    }
+
+    let mut credited_custody: Vec<LegRecord> = Vec::new();
+    let guarded = legs
+        .iter()
+        .filter(|leg| entry.kind != EntryKind::Opening && leg.credit > 0 && leg.chain_id.is_some());
+    for leg in guarded {
+        if !credited_custody
+            .iter()
+            .any(|seen| seen.chain_id == leg.chain_id)
+        {
+            credited_custody.push(leg.clone());
+        }
+    }
+
+    let mut attempt = 0;
+    loop {
+        match try_post(db, &entry, &legs, &debited, &credited_custody).await {
+            Err(LedgerError::Surrealdb(error))
+                if db::is_conflict(&error) && attempt < db::CONFLICT_RETRIES =>
+            {
+                attempt += 1;
+                println!(
+                    "Retrying journal entry {} after a write conflict",
+                    entry.reference
+                );
+                db::conflict_backoff(attempt).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn try_post(
+    db: &Surreal<Any>,
+    entry: &JournalEntry,
+    legs: &[LegRecord],
+    debited: &[LegRecord],
+    credited_custody: &[LegRecord],
+) -> Result<(), LedgerError> {
+    // Bumping a row per debited account, and per chain whose custody is
+    // credited, makes two concurrent posts against the same account conflict,
+    // so only one of them can pass the balance checks below and commit.
+    let mut response = db
+        .query(
+            "
+            BEGIN TRANSACTION;
+            FOR $leg IN $debited {
+                UPSERT type::thing('ledger_accounts', [$leg.account, $leg.userId, $tokenSymbol])
+                    SET version = (version OR 0) + 1;
+            };
+            FOR $leg IN $custody {
+                UPSERT type::thing('ledger_accounts', [$leg.account, $leg.chainId, $tokenSymbol])
+                    SET version = (version OR 0) + 1;
+            };
+            LET $journal = (CREATE ONLY journal SET
+                kind = type::string($kind),
+                token = type::string($tokenSymbol),
+                reference = type::string($reference),
+                createdAt = time::now()
+                RETURN VALUE id);
+            FOR $leg IN $legs {
+                CREATE ledger SET
+                    journal = $journal,
+                    kind = type::string($kind),
+                    token = type::string($tokenSymbol),
+                    reference = type::string($reference),
+                    account = type::string($leg.account),
+                    userId = IF $leg.userId THEN type::thing($leg.userId) END,
+                    chainId = IF $leg.chainId THEN $leg.chainId END,
+                    debit = type::number($leg.debit),
+                    credit = type::number($leg.credit),
+                    createdAt = time::now();
+            };
+            FOR $leg IN $debited {
+                IF math::sum(SELECT VALUE credit - debit FROM ledger
+                    WHERE account = $leg.account AND userId = type::thing($leg.userId)
+                    AND token = $tokenSymbol) < 0 {
+                    THROW 'insufficient funds in ' + $leg.account + ' of ' + $leg.userId;
+                };
+            };
+            FOR $leg IN $custody {
+                IF math::sum(SELECT VALUE debit - credit FROM ledger
+                    WHERE account = $leg.account AND chainId = $leg.chainId
+                    AND token = $tokenSymbol) < 0 {
+                    THROW 'insufficient custody on chain ' + <string> $leg.chainId + ' of ' + $tokenSymbol;
+                };
+            };
+            COMMIT TRANSACTION;
+            ",
+        )
+        .bind(("kind", entry.kind))
+        .bind(("tokenSymbol", entry.token.clone()))
+        .bind(("reference", entry.reference.clone()))
+        .bind(("legs", legs.to_vec()))
+        .bind(("debited", debited.to_vec()))
+        .bind(("custody", credited_custody.to_vec()))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        if db::violates_index(&error, "journal_reference") {
+            return Err(LedgerError::Duplicate {
+                kind: entry.kind,
+                reference: entry.reference.clone(),
+            });
+        }
+        if error.to_string().contains("insufficient funds") {
+            return Err(LedgerError::InsufficientFunds(entry.reference.clone()));
+        }
+        let short_chain = credited_custody
+            .iter()
+            .filter_map(|leg| leg.chain_id)
+            .find(|chain_id| {
+                error
+                    .to_string()
+                    .contains(&format!("insufficient custody on chain {} of", chain_id))
+            });
+        if let Some(chain_id) = short_chain {
+            return Err(LedgerError::InsufficientCustody {
+                reference: entry.reference.clone(),
+                chain_id,
+            });
+        }
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Sums the legs posted to one of a user's accounts in `token`.
+pub async fn balance(
+    db: &Surreal<Any>,
+    account: &Account,
+    token: &str,
+) -> Result<Amount, LedgerError> {
+    let user_id = account.user_id().map(str::to_string);
+    let chain_id = account.chain_id();
+
+    let balance = db
+        .query(
+            "
+            RETURN math::sum(SELECT VALUE credit - debit FROM ledger
+            WHERE account = type::string($account)
+            AND userId = IF $userId THEN type::thing($userId) END
+            AND chainId = IF $chainId THEN $chainId END
+            AND token = type::string($tokenSymbol));
+            ",
+        )
+        .bind(("account", account.code()))
+        .bind(("userId", user_id))
+        .bind(("chainId", chain_id))
+        .bind(("tokenSymbol", token.to_string()))
+        .await?
+        .take::<Option<Amount>>(0)?
+        .unwrap_or_default();
+
+    Ok(balance)
+}
+
+/// Funds a user can spend right now. Funds locked behind open offers have
+/// already been moved to their escrow account and are not included.
+pub async fn available_balance(
+    db: &Surreal<Any>,
+    user_id: &str,
+    token: &str,
+) -> Result<Amount, LedgerError> {
+    balance(db, &Account::Available(user_id.to_string()), token).await
+}
+
+/// Every leg posted to any of a user's accounts, newest first.
+pub async fn history(db: &Surreal<Any>, user_id: &str) -> Result<Vec<LedgerEntry>, LedgerError> {
+    let entries = db
+        .query(
+            "
+            SELECT id, journal, kind, reference, token, account, debit, credit, createdAt
+            FROM ledger
+            WHERE userId = type::thing($userId)
+            ORDER BY createdAt DESC;
+            ",
+        )
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Vec<LedgerEntry>>(0)?;
+
+    Ok(entries)
+}
+
+/// Moves balances still stored on `user.balance` into the ledger in `token`,
+/// held in custody on `chain_id`, and removes the field, so it can no longer drift from the ledger.
+///
+/// The legacy field was never debited when an offer was made or filled;
+/// reads subtracted open offers and what closed offers sold instead. So the
+/// opening entry carries over the field less the sales of closed offers, and
+/// each offer that is not closed yet gets its amount and fee moved to the
+/// maker's escrow, where the trades it already completed are paid out of, as
+/// if it had been made under escrow. These are all opening entries, which the
+/// balance guard leaves alone: a user whose offers were worth more than their
+/// balance keeps the shortfall as a negative available balance instead of
+/// stopping the server from starting.
+///
+/// Every entry is posted at most once, so a migration cut short picks up where
+/// it stopped on the next start.
+pub async fn migrate_legacy_balances(
+    db: &Surreal<Any>,
+    token: &str,
+    chain_id: u64,
+) -> Result<(), LedgerError> {
+    let users = db
+        .query("SELECT VALUE id FROM user WHERE balance != NONE")
+        .await?
+        .take::<Vec<Thing>>(0)?;
+
+    for user_id in users {
+        let user_id = user_id.to_string();
+        let mut response = db
+            .query(
+                "
+                SELECT VALUE balance FROM ONLY type::thing($id);
+                RETURN math::sum(SELECT VALUE amount + takerFee + makerFee FROM transactions
+                    WHERE offerId.userId = type::thing($id) AND offerId.status = 'closed'
+                    AND status = 'successful');
+                SELECT VALUE [<string> id, amount + (fee OR 0)] FROM offers
+                    WHERE userId = type::thing($id) AND status != 'closed';
+                SELECT VALUE [<string> id, amount + takerFee + makerFee] FROM transactions
+                    WHERE offerId.userId = type::thing($id) AND offerId.status != 'closed'
+                    AND status = 'successful';
+                ",
+            )
+            .bind(("id", user_id.clone()))
+            .await?;
+        let legacy_balance = response.take::<Option<Amount>>(0)?.unwrap_or_default();
+        let sold = response.take::<Option<Amount>>(1)?.unwrap_or_default();
+        let offers = response.take::<Vec<(String, Amount)>>(2)?;
+        let filled = response.take::<Vec<(String, Amount)>>(3)?;
+
+        let available = Account::Available(user_id.clone());
+        let escrow = Account::Escrow(user_id.clone());
+        let opening = legacy_balance.checked_sub(sold)?;
+        println!("Migrating legacy balance {} of {}", opening, user_id);
+
+        let reference = format!("opening:{}", user_id);
+        if opening.is_positive() {
+            post_opening(
+                db,
+                token,
+                reference,
+                Account::Custody(chain_id),
+                available.clone(),
+                opening,
+            )
+            .await?;
+        } else if opening.is_negative() {
+            let owed = Amount::ZERO.checked_sub(opening)?;
+            post_opening(
+                db,
+                token,
+                reference,
+                available.clone(),
+                Account::Custody(chain_id),
+                owed,
+            )
+            .await?;
+        }
+
+        let mut locked = Amount::ZERO;
+        for (offer_id, amount) in offers {
+            post_opening(
+                db,
+                token,
+                offer_id,
+                available.clone(),
+                escrow.clone(),
+                amount,
+            )
+            .await?;
+            locked = locked.checked_add(amount)?;
+        }
+        // The takers of these trades were paid outside the ledger.
+        for (trade_id, amount) in filled {
+            post_opening(
+                db,
+                token,
+                trade_id,
+                escrow.clone(),
+                Account::Custody(chain_id),
+                amount,
+            )
+            .await?;
+        }
+
+        let left = opening.checked_sub(locked)?;
+        if left.is_negative() {
+            println!(
+                "Legacy offers of {} lock {} more than their balance; their available balance starts at {}",
+                user_id,
+                Amount::ZERO.checked_sub(left)?,
+                left
+            );
+        }
+
+        db.query("UPDATE type::thing($id) UNSET balance")
+            .bind(("id", user_id))
+            .await?
+            .check()?;
+    }
+
+    Ok(())
+}
+
+/// Posts an opening transfer, treating one that was already posted as done.
+async fn post_opening(
+    db: &Surreal<Any>,
+    token: &str,
+    reference: String,
+    from: Account,
+    to: Account,
+    amount: Amount,
+) -> Result<(), LedgerError> {
+    let entry = JournalEntry::new(EntryKind::Opening, token, reference).transfer(from, to, amount);
+
+    match post(db, entry).await {
+        Ok(()) | Err(LedgerError::Duplicate { .. }) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
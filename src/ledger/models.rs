@@ -0,0 +1,202 @@
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use surrealdb::sql::{Datetime, Thing};
+use thiserror::Error;
+
+use crate::money::models::{Amount, MoneyError};
+
+/// What a journal entry records. Stored as the `kind` of both the journal
+/// header and each of its legs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    /// Carries a balance from the old mutable `user.balance` field.
+    Opening,
+    Deposit,
+    Withdrawal,
+    /// Returns a failed withdrawal to the user.
+    WithdrawalRefund,
+    OfferLock,
+    OfferRelease,
+    TradeSettlement,
+    Fee,
+}
+
+/// An account a leg is posted to. User accounts are liabilities of the
+/// platform, so their balance is `credit - debit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Account {
+    /// Funds the user can spend.
+    Available(String),
+    /// Funds locked behind the user's open offers.
+    Escrow(String),
+    /// Tokens held by the platform's wallets on a chain, by chain id.
+    Custody(u64),
+    /// Fees collected by the platform.
+    Fees,
+}
+
+impl Account {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Account::Available(_) => "available",
+            Account::Escrow(_) => "escrow",
+            Account::Custody(_) => "custody",
+            Account::Fees => "fees",
+        }
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        match self {
+            Account::Available(user_id) | Account::Escrow(user_id) => Some(user_id),
+            Account::Custody(_) | Account::Fees => None,
+        }
+    }
+
+    pub fn chain_id(&self) -> Option<u64> {
+        match self {
+            Account::Custody(chain_id) => Some(*chain_id),
+            Account::Available(_) | Account::Escrow(_) | Account::Fees => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Leg {
+    pub account: Account,
+    pub debit: i128,
+    pub credit: i128,
+}
+
+impl Leg {
+    pub fn debit(account: Account, amount: i128) -> Self {
+        Leg {
+            account,
+            debit: amount,
+            credit: 0,
+        }
+    }
+
+    pub fn credit(account: Account, amount: i128) -> Self {
+        Leg {
+            account,
+            debit: 0,
+            credit: amount,
+        }
+    }
+}
+
+/// A balanced set of legs posted atomically. All legs are in the same token.
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub kind: EntryKind,
+    /// Symbol of the token the legs are in.
+    pub token: String,
+    /// Identifies what the entry is about, e.g. a transaction hash or an offer id.
+    pub reference: String,
+    pub legs: Vec<Leg>,
+}
+
+impl JournalEntry {
+    pub fn new(kind: EntryKind, token: &str, reference: impl Into<String>) -> Self {
+        JournalEntry {
+            kind,
+            token: token.to_string(),
+            reference: reference.into(),
+            legs: Vec::new(),
+        }
+    }
+
+    /// Moves `amount` from the debited account to the credited one.
+    pub fn transfer(mut self, from: Account, to: Account, amount: Amount) -> Self {
+        self.legs.push(Leg::debit(from, amount.base_units()));
+        self.legs.push(Leg::credit(to, amount.base_units()));
+        self
+    }
+}
+
+/// The shape a leg is bound to the database in.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct LegRecord {
+    pub account: &'static str,
+    #[serde(rename = "userId")]
+    pub user_id: Option<String>,
+    #[serde(rename = "chainId")]
+    pub chain_id: Option<u64>,
+    pub debit: i128,
+    pub credit: i128,
+}
+
+impl From<&Leg> for LegRecord {
+    fn from(leg: &Leg) -> Self {
+        LegRecord {
+            account: leg.account.code(),
+            user_id: leg.account.user_id().map(str::to_string),
+            chain_id: leg.account.chain_id(),
+            debit: leg.debit,
+            credit: leg.credit,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub journal: Thing,
+    pub kind: EntryKind,
+    pub reference: String,
+    pub token: String,
+    pub account: String,
+    pub debit: i128,
+    pub credit: i128,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+}
+
+#[derive(Debug, Error)]
+pub enum LedgerError {
+    #[error("journal entry {reference} is not balanced: debits {debits}, credits {credits}")]
+    Unbalanced {
+        reference: String,
+        debits: i128,
+        credits: i128,
+    },
+
+    #[error("{kind:?} journal entry {reference} was already posted")]
+    Duplicate { kind: EntryKind, reference: String },
+
+    #[error("journal entry {0} would overdraw an account")]
+    InsufficientFunds(String),
+
+    #[error("journal entry {reference} would pay out more than is held on chain {chain_id}")]
+    InsufficientCustody { reference: String, chain_id: u64 },
+
+    #[error("journal entry {0} has a negative leg")]
+    NegativeLeg(String),
+
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error(transparent)]
+    Surrealdb(#[from] surrealdb::Error),
+}
+
+impl LedgerError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            LedgerError::InsufficientFunds(_) | LedgerError::InsufficientCustody { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            LedgerError::Duplicate { .. } => StatusCode::CONFLICT,
+            LedgerError::Money(error) => error.status_code(),
+            LedgerError::Unbalanced { .. }
+            | LedgerError::NegativeLeg(_)
+            | LedgerError::Surrealdb(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -1,85 +1,311 @@
-use args::Args;
+use std::str::FromStr;
+use std::time::Duration;
+
+use alloy::primitives::Address;
+use alloy::providers::Provider;
+use alloy::transports::TransportError;
+use args::{Args, FeePolicyKind};
 use axum::{routing::get, Router};
+use chain::models::{ChainConfig, ChainError};
+use chain::registry::ChainRegistry;
 use clap::Parser;
-use surrealdb::engine::remote::ws::{Client, Ws};
+use hd_wallet::{HdWallet, HdWalletError};
+use hot_wallet::signer::SignerError;
+use hot_wallet::HotWallet;
+use ledger::models::LedgerError;
+use money::models::{Amount, MoneyError};
+use money::Token;
+use rpc::models::{RetryPolicy, RpcError};
+use rpc::RpcPool;
+use surrealdb::engine::any::{self, Any};
 use surrealdb::opt::auth::Root;
-use surrealdb::{Error, Surreal};
+use surrealdb::Surreal;
 use thiserror::Error;
+use tokens::models::{RegisteredToken, TokenError};
+use tokens::TokenRegistry;
+use wallets::models::WalletError;
+use withdrawals::models::{FeePolicy, WithdrawalLimits};
 
 pub mod api;
-mod args;
+pub mod args;
+pub mod chain;
+pub mod db;
+pub mod deposits;
+pub mod events;
+pub mod expiry;
+pub mod hd_wallet;
+pub mod hot_wallet;
+pub mod ledger;
+pub mod money;
+pub mod offers;
+pub mod rpc;
+pub mod tokens;
+pub mod trades;
+pub mod wallets;
+pub mod withdrawals;
+pub mod worker;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
-    pub database: Surreal<Client>,
+    pub database: Surreal<Any>,
     pub jwt_secret: String,
-    pub alchemy_rpc_url: String,
-    pub confirming_blocks: u64,
-    pub private_key: String,
-    pub token_address: String,
+    pub chains: ChainRegistry,
+    pub tokens: TokenRegistry,
     pub wallet_address: String,
+    pub hd_wallet: Option<HdWallet>,
+    pub deposit_poll_interval_secs: u64,
+    pub deposit_timeout_secs: u64,
+    pub indexer_start_block: Option<u64>,
+    pub indexer_batch_blocks: u64,
+    pub sweep_interval_secs: u64,
+    pub sweep_min_amount: Amount,
+    pub expiry_interval_secs: u64,
+    pub withdrawal_poll_interval_secs: u64,
+    pub withdrawal_stuck_after_secs: u64,
+    pub withdrawal_fee_bump_percent: u128,
+    /// In wei.
+    pub withdrawal_max_fee_per_gas: u128,
+    pub withdrawal_fee_policy: FeePolicy,
+    pub withdrawal_limits: WithdrawalLimits,
+    pub withdrawal_address_waiting_secs: u64,
+    /// In basis points of the amount traded.
+    pub trade_taker_fee_bps: u32,
+    pub admin_addresses: Vec<Address>,
 }
 
 impl AppState {
-    pub async fn new(
-        address: &str,
-        username: &str,
-        password: &str,
-        namespace: &str,
-        database: &str,
-        jwt_secret: &str,
-        alchemy_rpc_url: &str,
-        confirming_blocks: u64,
-        private_key: &str,
-        token_address: &str,
-        wallet_address: &str,
-    ) -> Result<Self, Error> {
-        let client = Surreal::new::<Ws>(address).await?;
-        client.signin(Root { username, password }).await?;
-        client.use_ns(namespace).use_db(database).await?;
+    pub async fn new(args: &Args) -> Result<Self, ServerError> {
+        let client = any::connect(format!("ws://{}", args.surrealdb_address)).await?;
+        client
+            .signin(Root {
+                username: &args.surrealdb_username,
+                password: &args.surrealdb_password,
+            })
+            .await?;
+        client
+            .use_ns(&args.surrealdb_namespace)
+            .use_db(&args.surrealdb_database)
+            .await?;
+
+        let rpc_policy = RetryPolicy {
+            request_timeout: Duration::from_secs(args.rpc_request_timeout_secs),
+            max_retries: args.rpc_max_retries,
+            initial_backoff: Duration::from_millis(args.rpc_initial_backoff_ms),
+            max_backoff: Duration::from_millis(args.rpc_max_backoff_ms),
+            unhealthy_after: args.rpc_unhealthy_after.max(1),
+            health_check_interval: Duration::from_secs(args.rpc_health_check_interval_secs),
+            max_block_lag: args.rpc_max_block_lag,
+        };
+        let rpc = RpcPool::new(&args.alchemy_rpc_url, rpc_policy)?;
+        let provider = rpc.provider();
+        let chain_id = provider.get_chain_id().await?;
+        let token_address = Address::from_str(&args.token_address)
+            .map_err(|_| ServerError::InvalidTokenAddress(args.token_address.clone()))?;
+        let token = Token::load(&provider, token_address, args.token_decimals).await?;
+
+        println!("Connected to chain {}", chain_id);
+
+        let primary = ChainConfig {
+            chain_id,
+            rpc_urls: args.alchemy_rpc_url.clone(),
+            confirmations: args.confirming_blocks,
+            block_time_secs: args.block_time_secs,
+            explorer_url: args.explorer_url.clone(),
+            enabled: true,
+        };
+        let signer = HotWallet::signer_from_args(args)?;
+        let chains = ChainRegistry::load(&client, primary, rpc, rpc_policy, signer).await?;
+
+        let default_token = RegisteredToken {
+            symbol: args.token_symbol.clone(),
+            address: token.address,
+            decimals: token.decimals,
+            chain_id,
+            enabled: true,
+        };
+        let tokens = TokenRegistry::load(&client, &chains, default_token).await?;
+
+        let hd_wallet = args
+            .deposit_hd_seed
+            .as_deref()
+            .map(HdWallet::from_seed_hex)
+            .transpose()?;
+        let withdrawal_fee_policy = match args.withdrawal_fee_policy {
+            FeePolicyKind::Flat => FeePolicy::Flat(
+                Amount::from_base_units(args.withdrawal_fee_flat).ensure_not_negative()?,
+            ),
+            FeePolicyKind::Percentage => FeePolicy::Percentage(args.withdrawal_fee_bps),
+            FeePolicyKind::Gas => FeePolicy::Gas {
+                eth_price: args
+                    .withdrawal_fee_eth_price
+                    .map(Amount::from_base_units)
+                    .ok_or(ServerError::MissingConfig("--withdrawal-fee-eth-price"))?,
+                margin_percent: args.withdrawal_fee_gas_margin_percent,
+            },
+        };
+        let admin_addresses = args
+            .admin_addresses
+            .iter()
+            .map(|address| {
+                Address::from_str(address)
+                    .map_err(|_| ServerError::InvalidAdminAddress(address.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
         Ok(AppState {
             database: client,
-            jwt_secret: jwt_secret.to_string(),
-            alchemy_rpc_url: alchemy_rpc_url.to_string(),
-            confirming_blocks,
-            private_key: private_key.to_string(),
-            token_address: token_address.to_string(),
-            wallet_address: wallet_address.to_string(),
+            jwt_secret: args.jwt_secret.clone(),
+            chains,
+            tokens,
+            wallet_address: args.wallet_address.clone(),
+            hd_wallet,
+            deposit_poll_interval_secs: args.deposit_poll_interval_secs,
+            deposit_timeout_secs: args.deposit_timeout_secs,
+            indexer_start_block: args.indexer_start_block,
+            indexer_batch_blocks: args.indexer_batch_blocks.max(1),
+            sweep_interval_secs: args.sweep_interval_secs.max(1),
+            sweep_min_amount: Amount::from_base_units(args.sweep_min_amount).ensure_positive()?,
+            expiry_interval_secs: args.expiry_interval_secs.max(1),
+            withdrawal_poll_interval_secs: args.withdrawal_poll_interval_secs,
+            withdrawal_stuck_after_secs: args.withdrawal_stuck_after_secs,
+            withdrawal_fee_bump_percent: u128::from(args.withdrawal_fee_bump_percent.max(10)),
+            withdrawal_max_fee_per_gas: u128::from(args.withdrawal_max_fee_per_gas_gwei)
+                * 1_000_000_000,
+            withdrawal_fee_policy,
+            withdrawal_limits: WithdrawalLimits {
+                daily: args.withdrawal_daily_limit.map(Amount::from_base_units),
+                monthly: args.withdrawal_monthly_limit.map(Amount::from_base_units),
+                approval_threshold: args
+                    .withdrawal_approval_threshold
+                    .map(Amount::from_base_units),
+            },
+            withdrawal_address_waiting_secs: args.withdrawal_address_waiting_secs,
+            trade_taker_fee_bps: args.trade_taker_fee_bps,
+            admin_addresses,
         })
     }
 }
 
 #[derive(Debug, Error)]
-enum ServerError {
+pub enum ServerError {
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+
+    #[error(transparent)]
+    Ledger(#[from] Box<LedgerError>),
+
+    #[error(transparent)]
+    Wallet(#[from] WalletError),
+
     #[error(transparent)]
-    Surrealdb(#[from] surrealdb::Error),
+    HdWallet(#[from] HdWalletError),
+
+    #[error(transparent)]
+    Signer(#[from] SignerError),
+
+    #[error(transparent)]
+    Rpc(#[from] Box<TransportError>),
+
+    #[error(transparent)]
+    RpcPool(#[from] RpcError),
+
+    #[error("{0} is required by the configured withdrawal fee policy")]
+    MissingConfig(&'static str),
+
+    #[error("invalid token address {0}")]
+    InvalidTokenAddress(String),
+
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+
+    #[error("invalid admin address {0}")]
+    InvalidAdminAddress(String),
 
     #[error(transparent)]
     Io(#[from] std::io::Error),
 }
 
+impl From<surrealdb::Error> for ServerError {
+    fn from(error: surrealdb::Error) -> Self {
+        ServerError::Surrealdb(Box::new(error))
+    }
+}
+
+impl From<TransportError> for ServerError {
+    fn from(error: TransportError) -> Self {
+        ServerError::Rpc(Box::new(error))
+    }
+}
+
+impl From<LedgerError> for ServerError {
+    fn from(error: LedgerError) -> Self {
+        ServerError::Ledger(Box::new(error))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), ServerError> {
     let args = Args::parse();
 
-    let app_state = AppState::new(
-        &args.surrealdb_address,
-        &args.surrealdb_username,
-        &args.surrealdb_password,
-        &args.surrealdb_namespace,
-        &args.surrealdb_database,
-        &args.jwt_secret,
-        &args.alchemy_rpc_url,
-        args.confirming_blocks,
-        &args.private_key,
-        &args.token_address,
-        &args.wallet_address,
-    )
-    .await?;
+    let app_state = AppState::new(&args).await?;
+
+    db::define_schema(&app_state.database).await?;
+    let default_token = &app_state.tokens.default_token().symbol;
+    let primary_chain = app_state.chains.primary().id();
+    ledger::migrate_legacy_balances(&app_state.database, default_token, primary_chain).await?;
+    tokens::migrate_untokened(&app_state.database, default_token).await?;
+    chain::registry::migrate_unchained(&app_state.database, primary_chain).await?;
+    trades::migrate_legacy_statuses(&app_state.database).await?;
+    wallets::link_signup_addresses(&app_state.database).await?;
+
+    for chain in app_state.chains.all() {
+        let chain_id = chain.id();
+
+        let rpc = chain.rpc.clone();
+        tokio::spawn(worker::supervise(
+            format!("chain {} rpc health", chain_id),
+            move || rpc::run_health_checks(rpc.clone()),
+        ));
+
+        let deposit_state = app_state.clone();
+        tokio::spawn(worker::supervise(
+            format!("chain {} deposit", chain_id),
+            move || deposits::worker::run(deposit_state.clone(), chain_id),
+        ));
+
+        let indexer_state = app_state.clone();
+        tokio::spawn(worker::supervise(
+            format!("chain {} indexer", chain_id),
+            move || deposits::indexer::run(indexer_state.clone(), chain_id),
+        ));
+
+        let sweeper_state = app_state.clone();
+        tokio::spawn(worker::supervise(
+            format!("chain {} sweeper", chain_id),
+            move || deposits::sweeper::run(sweeper_state.clone(), chain_id),
+        ));
+
+        let withdrawal_state = app_state.clone();
+        tokio::spawn(worker::supervise(
+            format!("chain {} withdrawal", chain_id),
+            move || withdrawals::worker::run(withdrawal_state.clone(), chain_id),
+        ));
+    }
+
+    let expiry_state = app_state.clone();
+    tokio::spawn(worker::supervise("expiry".to_string(), move || {
+        expiry::run(expiry_state.clone())
+    }));
 
     let app = Router::new()
         .route("/", get(|| async { "Hello, World!" }))
+        .nest("/admin", api::admin::router(&app_state))
         .nest("/auth", api::auth::router(&app_state))
         .nest("/private", api::private::router(&app_state))
         .nest("/public", api::public::router(&app_state));
@@ -0,0 +1,110 @@
+//! Amounts of the platform's token and their on-chain representation.
+//!
+//! Everything off-chain is kept as an [`Amount`] in a fixed base unit. The
+//! token's own decimals are only needed at the edges, when a Transfer is
+//! credited or a transfer is sent, and [`Token`] converts between the two
+//! with checked arithmetic. A conversion that would drop digits is rejected
+//! rather than rounded, unless the digits are handed back as dust.
+
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, U256};
+use alloy::providers::{DynProvider, Provider};
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol_types::SolCall;
+use models::{Amount, MoneyError};
+
+use crate::api::private::IERC20;
+
+pub mod models;
+
+/// The ERC-20 token balances are held in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub address: Address,
+    pub decimals: u8,
+}
+
+impl Token {
+    /// Uses `decimals` if given, and otherwise reads them from the contract.
+    pub async fn load(
+        provider: &DynProvider,
+        address: Address,
+        decimals: Option<u8>,
+    ) -> Result<Token, MoneyError> {
+        let decimals = match decimals {
+            Some(decimals) => decimals,
+            None => {
+                let tx = TransactionRequest::default()
+                    .with_to(address)
+                    .with_call(&IERC20::decimalsCall {});
+                let output = provider.call(tx).await?;
+                IERC20::decimalsCall::abi_decode_returns(&output)
+                    .map_err(|err| MoneyError::Decimals(err.to_string()))?
+            }
+        };
+
+        Ok(Token { address, decimals })
+    }
+
+    /// Converts `value` in the token's smallest unit to an [`Amount`].
+    pub fn to_amount(&self, value: U256) -> Result<Amount, MoneyError> {
+        let (amount, dust) = self.to_amount_and_dust(value)?;
+        if !dust.is_zero() {
+            return Err(MoneyError::PrecisionLoss {
+                value: value.to_string(),
+                decimals: Amount::DECIMALS,
+            });
+        }
+
+        Ok(amount)
+    }
+
+    /// Converts as much of `value` as an [`Amount`] can hold, and returns
+    /// the rest, in the token's smallest unit, alongside it. Only a token
+    /// with more decimals than [`Amount`] leaves any.
+    pub fn to_amount_and_dust(&self, value: U256) -> Result<(Amount, U256), MoneyError> {
+        let (scaled, dust) = if self.decimals >= Amount::DECIMALS {
+            let factor = factor(self.decimals - Amount::DECIMALS)?;
+            value.div_rem(factor)
+        } else {
+            let factor = factor(Amount::DECIMALS - self.decimals)?;
+            (
+                value.checked_mul(factor).ok_or(MoneyError::Overflow)?,
+                U256::ZERO,
+            )
+        };
+
+        let amount = i128::try_from(scaled)
+            .map(Amount::from_base_units)
+            .map_err(|_| MoneyError::Overflow)?;
+
+        Ok((amount, dust))
+    }
+
+    /// Converts `amount` to the token's smallest unit, as sent in a transfer.
+    pub fn to_token_units(&self, amount: Amount) -> Result<U256, MoneyError> {
+        let units = u128::try_from(amount.base_units()).map_err(|_| MoneyError::Negative)?;
+        let units = U256::from(units);
+
+        if self.decimals >= Amount::DECIMALS {
+            let factor = factor(self.decimals - Amount::DECIMALS)?;
+            units.checked_mul(factor).ok_or(MoneyError::Overflow)
+        } else {
+            let factor = factor(Amount::DECIMALS - self.decimals)?;
+            let (quotient, remainder) = units.div_rem(factor);
+            if !remainder.is_zero() {
+                return Err(MoneyError::PrecisionLoss {
+                    value: amount.to_string(),
+                    decimals: self.decimals,
+                });
+            }
+            Ok(quotient)
+        }
+    }
+}
+
+fn factor(exponent: u8) -> Result<U256, MoneyError> {
+    U256::from(10u8)
+        .checked_pow(U256::from(exponent))
+        .ok_or(MoneyError::Overflow)
+}
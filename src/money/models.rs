@@ -0,0 +1,129 @@
+use std::fmt;
+
+use alloy::transports::TransportError;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// An amount of the platform's token in its base unit, a millionth of a
+/// token. Balances, offers, trades and fees are all kept in this unit no
+/// matter how many decimals the token itself has.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Amount(i128);
+
+impl Amount {
+    /// Decimals of the base unit.
+    pub const DECIMALS: u8 = 6;
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_base_units(units: i128) -> Self {
+        Amount(units)
+    }
+
+    pub const fn base_units(self) -> i128 {
+        self.0
+    }
+
+    pub const fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Fails unless the amount is above zero, as anything that is moved must be.
+    pub fn ensure_positive(self) -> Result<Amount, MoneyError> {
+        if self.is_positive() {
+            Ok(self)
+        } else {
+            Err(MoneyError::NotPositive)
+        }
+    }
+
+    /// Fails if the amount is below zero, as fees must not be.
+    pub fn ensure_not_negative(self) -> Result<Amount, MoneyError> {
+        if self.is_negative() {
+            Err(MoneyError::Negative)
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub fn checked_add(self, other: Amount) -> Result<Amount, MoneyError> {
+        self.0
+            .checked_add(other.0)
+            .map(Amount)
+            .ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Result<Amount, MoneyError> {
+        self.0
+            .checked_sub(other.0)
+            .map(Amount)
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// `bps` basis points of the amount, rounded up.
+    pub fn checked_bps(self, bps: u32) -> Result<Amount, MoneyError> {
+        self.0
+            .checked_mul(i128::from(bps))
+            .and_then(|scaled| scaled.checked_add(9_999))
+            .map(|scaled| Amount(scaled / 10_000))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Amount>) -> Result<Amount, MoneyError> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |sum, amount| sum.checked_add(amount))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MoneyError {
+    #[error("amount is out of range")]
+    Overflow,
+
+    #[error("amount must not be negative")]
+    Negative,
+
+    #[error("amount must be positive")]
+    NotPositive,
+
+    #[error("{value} cannot be represented with {decimals} decimals without losing precision")]
+    PrecisionLoss { value: String, decimals: u8 },
+
+    #[error("token decimals could not be read: {0}")]
+    Decimals(String),
+
+    #[error(transparent)]
+    Rpc(#[from] Box<TransportError>),
+}
+
+impl From<TransportError> for MoneyError {
+    fn from(error: TransportError) -> Self {
+        MoneyError::Rpc(Box::new(error))
+    }
+}
+
+impl MoneyError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            MoneyError::Overflow
+            | MoneyError::Negative
+            | MoneyError::NotPositive
+            | MoneyError::PrecisionLoss { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            MoneyError::Decimals(_) | MoneyError::Rpc(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Offers and the escrow behind them.
+//!
+//! Everything an offer can sell, its amount plus the maker's fee, is moved
+//! from the maker's available balance to their escrow when it is created, so
+//! a maker can never offer more than they have. Trades are paid out of that
+//! escrow as they settle. Once an offer is closed, whatever no trade took is
+//! released back to the maker. A release that fails is retried by the expiry
+//! job until it goes through; see [`release_closed`].
+
+use std::str::FromStr;
+
+use models::{OfferError, OfferEscrow, OfferTerms};
+use surrealdb::engine::any::Any;
+use surrealdb::sql::{Id, Thing};
+use surrealdb::Surreal;
+
+use crate::api::private::models::CreateOfferRequest;
+use crate::events::EventKind;
+use crate::ledger::models::{Account, EntryKind, JournalEntry, LedgerError};
+use crate::{db, ledger};
+
+pub mod models;
+
+/// Locks the offer's amount and fee in the maker's escrow and opens it. Fails
+/// without creating anything if the maker's available balance does not cover
+/// the lock.
+pub async fn create(
+    db: &Surreal<Any>,
+    user_id: &str,
+    request: CreateOfferRequest,
+) -> Result<Thing, OfferError> {
+    // Only sell offers are backed by escrow; a maker buying crypto has nothing
+    // to lock.
+    if !request.offer_type.eq_ignore_ascii_case("sell") {
+        return Err(OfferError::UnsupportedOfferType(request.offer_type));
+    }
+
+    let token = request.crypto_type.clone();
+    let locked = request
+        .amount
+        .ensure_positive()?
+        .checked_add(request.fee.ensure_not_negative()?)?;
+
+    // The lock is posted first, so there is never an open offer without the
+    // funds behind it. The ledger checks and debits the balance atomically.
+    let id = Thing::from(("offers", Id::ulid()));
+    let lock = ledger::post(
+        db,
+        JournalEntry::new(EntryKind::OfferLock, &token, id.to_string()).transfer(
+            Account::Available(user_id.to_string()),
+            Account::Escrow(user_id.to_string()),
+            locked,
+        ),
+    )
+    .await;
+
+    match lock {
+        Ok(()) => {}
+        Err(LedgerError::InsufficientFunds(_)) => {
+            return Err(OfferError::InsufficientBalance(locked))
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    let created = insert(db, &id, user_id, request).await;
+
+    match created {
+        Ok(Some(id)) => Ok(id),
+        // Nothing could ever fill or release an offer that was not recorded,
+        // so give the funds back straight away.
+        result => {
+            ledger::post(
+                db,
+                JournalEntry::new(EntryKind::OfferRelease, &token, id.to_string()).transfer(
+                    Account::Escrow(user_id.to_string()),
+                    Account::Available(user_id.to_string()),
+                    locked,
+                ),
+            )
+            .await?;
+            result?;
+            Err(OfferError::NotRecorded)
+        }
+    }
+}
+
+async fn insert(
+    db: &Surreal<Any>,
+    id: &Thing,
+    user_id: &str,
+    request: CreateOfferRequest,
+) -> Result<Option<Thing>, OfferError> {
+    let id = db
+        .query(
+            "
+            CREATE ONLY type::thing($id) SET
+                amount = type::number($amount),
+                fee = type::number($fee),
+                cryptoType = type::string($cryptoType),
+                currency = type::string($currency),
+                pricePerUnit = type::number($pricePerUnit),
+                value = type::number($value),
+                offerType = type::string($offerType),
+                revTag = type::string($revTag),
+                userId = type::thing($userId),
+                status = type::string('open'),
+                createdAt = time::now()
+            RETURN VALUE id;
+            ",
+        )
+        .bind(request)
+        .bind(("id", id.to_string()))
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Option<Thing>>(0)?;
+
+    Ok(id)
+}
+
+/// The terms of an offer and what trades have left of it. Cancelled and
+/// expired trades gave their part back, and so did pending ones past their
+/// expiry that the expiry job has not got to yet.
+pub async fn terms(db: &Surreal<Any>, offer_id: &str) -> Result<OfferTerms, OfferError> {
+    let id = match Thing::from_str(offer_id) {
+        Ok(thing) if thing.tb == "offers" => thing.to_string(),
+        _ => return Err(OfferError::NotFound(offer_id.to_string())),
+    };
+
+    db.query(
+        "
+        SELECT id, userId, status, cryptoType, currency, pricePerUnit, value, amount, fee,
+            amount - math::sum(SELECT VALUE amount + takerFee FROM transactions
+                WHERE offerId = $parent.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now())) AS remaining,
+            fee - math::sum(SELECT VALUE makerFee FROM transactions
+                WHERE offerId = $parent.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now())) AS feeRemaining
+        FROM ONLY type::thing($id);
+        ",
+    )
+    .bind(("id", id.clone()))
+    .await?
+    .take::<Option<OfferTerms>>(0)?
+    .ok_or(OfferError::NotFound(id))
+}
+
+/// Stops an offer from taking new trades. It is closed, and its escrow
+/// released, right away if no trade is open on it, or otherwise once the last
+/// one finishes.
+pub async fn close(db: &Surreal<Any>, offer_id: &str) -> Result<(), OfferError> {
+    let statuses = db
+        .query("
+            LET $open = COUNT(SELECT * FROM transactions WHERE status IN ['pending', 'payment_sent', 'payment_confirmed', 'disputed'] AND offerId = type::thing($id));
+            UPDATE offers SET status = IF $open > 0 THEN 'stopped' ELSE 'closed' END
+            WHERE id = type::thing($id) AND status != 'closed'
+            RETURN VALUE status;
+        ")
+        .bind(("id", offer_id.to_string()))
+        .await?
+        .take::<Vec<String>>(1)?;
+
+    if statuses.iter().any(|status| status == "closed") {
+        release_escrow(db, offer_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Closes stopped offers that have no open trades anymore and returns them.
+/// Each gets an event. Their escrow is left to [`release_closed`].
+pub async fn close_stopped(db: &Surreal<Any>) -> Result<Vec<Thing>, OfferError> {
+    let mut response = db
+        .query(
+            "
+            BEGIN TRANSACTION;
+            LET $closed = (UPDATE offers SET status = type::string('closed'), closedAt = time::now()
+            WHERE status == 'stopped'
+                AND COUNT(SELECT * FROM transactions WHERE status IN ['pending', 'payment_sent', 'payment_confirmed', 'disputed'] AND offerId = $parent.id) = 0
+            RETURN VALUE id);
+            FOR $offer IN $closed {
+                CREATE events SET kind = type::string($eventKind), subject = $offer, createdAt = time::now();
+            };
+            RETURN $closed;
+            COMMIT TRANSACTION;
+        ",
+        )
+        .bind(("eventKind", EventKind::OfferClosed))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        return Err(error.into());
+    }
+    let closed = response.take::<Vec<Thing>>(0)?;
+
+    Ok(closed)
+}
+
+/// Releases the escrow of every closed offer it has not been released from
+/// yet, including offers whose release failed when they were closed, and
+/// returns them. One offer failing does not hold up the others.
+pub async fn release_closed(db: &Surreal<Any>) -> Result<Vec<Thing>, OfferError> {
+    let unreleased = db
+        .query("SELECT VALUE id FROM offers WHERE status = 'closed' AND releasedAt = NONE")
+        .await?
+        .take::<Vec<Thing>>(0)?;
+
+    let mut released = Vec::with_capacity(unreleased.len());
+    for offer_id in unreleased {
+        match release_escrow(db, &offer_id.to_string()).await {
+            Ok(()) => released.push(offer_id),
+            Err(err) => println!("Error releasing escrow of offer {}: {:?}", offer_id, err),
+        }
+    }
+
+    Ok(released)
+}
+
+/// Returns the part of a closed offer's escrow that was never filled to the
+/// maker's available balance. Released at most once per offer; the offer is
+/// marked released once nothing is left to release.
+pub async fn release_escrow(db: &Surreal<Any>, offer_id: &str) -> Result<(), OfferError> {
+    let escrow = db
+        .query(
+            "
+            SELECT userId,
+            (SELECT VALUE token FROM ledger
+                WHERE account = 'escrow' AND reference = <string> $parent.id LIMIT 1)[0] AS token,
+            math::sum(SELECT VALUE credit - debit FROM ledger
+                WHERE account = 'escrow' AND reference = <string> $parent.id) AS locked,
+            math::sum(SELECT VALUE amount + takerFee + makerFee FROM transactions
+                WHERE offerId = $parent.id AND status NOT IN ['cancelled', 'expired']) AS filled
+            FROM ONLY type::thing($id);
+        ",
+        )
+        .bind(("id", offer_id.to_string()))
+        .await?
+        .take::<Option<OfferEscrow>>(0)?
+        .ok_or(OfferError::NotFound(offer_id.to_string()))?;
+
+    let remainder = escrow.locked.checked_sub(escrow.filled)?;
+    println!("Releasing {} from offer {}", remainder, offer_id);
+
+    // Offers from before escrow have nothing locked and no token.
+    let (true, Some(token)) = (remainder.is_positive(), escrow.token) else {
+        return mark_released(db, offer_id).await;
+    };
+
+    let user_id = escrow.user_id.to_string();
+    let release = ledger::post(
+        db,
+        JournalEntry::new(EntryKind::OfferRelease, &token, offer_id).transfer(
+            Account::Escrow(user_id.clone()),
+            Account::Available(user_id),
+            remainder,
+        ),
+    )
+    .await;
+
+    match release {
+        Ok(()) | Err(LedgerError::Duplicate { .. }) => mark_released(db, offer_id).await,
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn mark_released(db: &Surreal<Any>, offer_id: &str) -> Result<(), OfferError> {
+    db.query("UPDATE type::thing($id) SET releasedAt = time::now()")
+        .bind(("id", offer_id.to_string()))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use super::*;
+    use crate::money::models::Amount;
+
+    async fn memory_db() -> Surreal<Any> {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        db
+    }
+
+    fn offer(offer_type: &str) -> CreateOfferRequest {
+        CreateOfferRequest {
+            offer_type: offer_type.to_string(),
+            amount: Amount::from_base_units(1_000),
+            fee: Amount::from_base_units(10),
+            crypto_type: "USDT".to_string(),
+            currency: "EUR".to_string(),
+            price_per_unit: 1,
+            value: 1_000,
+            rev_tag: "maker".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn closed_offers_release_their_escrow_once() {
+        let db = memory_db().await;
+        let maker = "user:maker";
+        ledger::post(
+            &db,
+            JournalEntry::new(EntryKind::Opening, "USDT", "opening:user:maker").transfer(
+                Account::Custody(1),
+                Account::Available(maker.to_string()),
+                Amount::from_base_units(1_010),
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            create(&db, maker, offer("buy")).await,
+            Err(OfferError::UnsupportedOfferType(_))
+        ));
+        let offer_id = create(&db, maker, offer("sell")).await.unwrap();
+
+        // Closed, but the release never happened.
+        db.query("UPDATE type::thing($id) SET status = 'closed'")
+            .bind(("id", offer_id.to_string()))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+        assert_eq!(
+            ledger::available_balance(&db, maker, "USDT").await.unwrap(),
+            Amount::ZERO
+        );
+
+        assert_eq!(release_closed(&db).await.unwrap(), [offer_id]);
+        assert!(release_closed(&db).await.unwrap().is_empty());
+        assert_eq!(
+            ledger::available_balance(&db, maker, "USDT").await.unwrap(),
+            Amount::from_base_units(1_010)
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use surrealdb::sql::Thing;
+use thiserror::Error;
+
+use crate::ledger::models::LedgerError;
+use crate::money::models::{Amount, MoneyError};
+
+/// An offer as a trade on it sees it: the maker's terms and what is left of
+/// it.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfferTerms {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde(rename = "userId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub user_id: Thing,
+    pub status: String,
+    #[serde(rename = "cryptoType")]
+    pub crypto_type: String,
+    pub currency: String,
+    #[serde(rename = "pricePerUnit")]
+    pub price_per_unit: i128,
+    /// Of the whole offer, in `currency`.
+    pub value: i128,
+    pub amount: Amount,
+    /// The maker's fee for the whole offer.
+    pub fee: Amount,
+    /// What trades can still take, their amounts and taker fees together.
+    pub remaining: Amount,
+    /// What is left of `fee` after the maker fees of the trades so far.
+    #[serde(rename = "feeRemaining")]
+    pub fee_remaining: Amount,
+}
+
+/// What an offer holds in escrow and how much of it trades have taken.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfferEscrow {
+    #[serde(rename = "userId")]
+    pub user_id: Thing,
+    pub token: Option<String>,
+    pub locked: Amount,
+    pub filled: Amount,
+}
+
+#[derive(Debug, Error)]
+pub enum OfferError {
+    #[error("insufficient balance to lock {0} for the offer")]
+    InsufficientBalance(Amount),
+
+    #[error("{0} offers are not supported, only sell offers")]
+    UnsupportedOfferType(String),
+
+    #[error("offer could not be recorded")]
+    NotRecorded,
+
+    #[error("offer {0} not found")]
+    NotFound(String),
+
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error(transparent)]
+    Ledger(#[from] Box<LedgerError>),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<surrealdb::Error> for OfferError {
+    fn from(error: surrealdb::Error) -> Self {
+        OfferError::Surrealdb(Box::new(error))
+    }
+}
+
+impl From<LedgerError> for OfferError {
+    fn from(error: LedgerError) -> Self {
+        OfferError::Ledger(Box::new(error))
+    }
+}
+
+impl OfferError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            OfferError::InsufficientBalance(_) | OfferError::UnsupportedOfferType(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            OfferError::NotFound(_) => StatusCode::NOT_FOUND,
+            OfferError::Money(error) => error.status_code(),
+            OfferError::NotRecorded | OfferError::Ledger(_) | OfferError::Surrealdb(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+//! Access to a chain's JSON-RPC endpoints.
+//!
+//! An [`RpcPool`] is the transport under a chain's provider. Each request
+//! goes to the first healthy endpoint, in the order they were configured, and
+//! fails over to the next one if it errors, times out or is rate limited.
+//! Once every endpoint has failed, the pool backs off and goes round again,
+//! up to the policy's number of retries. Errors the node itself answers with,
+//! like a reverted call or a nonce that is too low, are passed through as
+//! they are.
+//!
+//! Endpoints that fail several requests in a row, or fall behind the others,
+//! are marked unhealthy and only tried as a last resort, until a request or a
+//! health check succeeds on them again. Every endpoint counts its requests,
+//! errors and latency; see [`RpcPool::stats`].
+//!
+//! Reads that have to agree with each other, like a receipt and the latest
+//! block its confirmations are counted against, go through
+//! [`RpcPool::pinned`] instead, so one lagging endpoint cannot answer half of
+//! them.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use alloy::rpc::client::RpcClient;
+use alloy::rpc::json_rpc::{RequestPacket, ResponsePacket};
+use alloy::transports::http::reqwest::{Client, Url};
+use alloy::transports::http::Http;
+use alloy::transports::{TransportError, TransportErrorKind, TransportFut};
+use models::{EndpointStats, RetryPolicy, RpcError};
+use tower::Service;
+
+pub mod models;
+
+/// The RPC endpoints of one chain. Cheap to clone; clones share endpoints
+/// and their stats.
+#[derive(Clone)]
+pub struct RpcPool {
+    endpoints: Arc<Vec<Endpoint>>,
+    policy: RetryPolicy,
+}
+
+struct Endpoint {
+    index: usize,
+    url: Url,
+    transport: Http<Client>,
+    /// Talks to this endpoint alone, for health checks.
+    probe: DynProvider,
+    healthy: AtomicBool,
+    consecutive_failures: AtomicU32,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    timeouts: AtomicU64,
+    latency_micros: AtomicU64,
+    latest_block: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl RpcPool {
+    pub fn new(urls: &[String], policy: RetryPolicy) -> Result<Self, RpcError> {
+        if urls.is_empty() {
+            return Err(RpcError::NoEndpoints);
+        }
+
+        let endpoints = urls
+            .iter()
+            .enumerate()
+            .map(|(index, url)| {
+                let url = Url::parse(url).map_err(|_| RpcError::InvalidUrl(url.clone()))?;
+                let transport = Http::new(url.clone());
+                let probe = ProviderBuilder::new()
+                    .on_client(RpcClient::new(transport.clone(), false))
+                    .erased();
+
+                Ok(Endpoint {
+                    index,
+                    url,
+                    transport,
+                    probe,
+                    healthy: AtomicBool::new(true),
+                    consecutive_failures: AtomicU32::new(0),
+                    requests: AtomicU64::new(0),
+                    errors: AtomicU64::new(0),
+                    timeouts: AtomicU64::new(0),
+                    latency_micros: AtomicU64::new(0),
+                    latest_block: AtomicU64::new(0),
+                    last_error: Mutex::new(None),
+                })
+            })
+            .collect::<Result<Vec<_>, RpcError>>()?;
+
+        Ok(RpcPool {
+            endpoints: Arc::new(endpoints),
+            policy,
+        })
+    }
+
+    /// A provider that sends everything through the pool.
+    pub fn provider(&self) -> DynProvider {
+        ProviderBuilder::new()
+            .on_client(RpcClient::new(self.clone(), false))
+            .erased()
+    }
+
+    /// A provider that sends everything to the endpoint first in line right
+    /// now, without failing over. A request it cannot answer fails, and the
+    /// whole sequence is read again later.
+    pub fn pinned(&self) -> DynProvider {
+        let index = self.candidates()[0].index;
+
+        ProviderBuilder::new()
+            .on_client(RpcClient::new(
+                Pinned {
+                    pool: self.clone(),
+                    index,
+                },
+                false,
+            ))
+            .erased()
+    }
+
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        self.endpoints.iter().map(Endpoint::stats).collect()
+    }
+
+    async fn request(&self, request: RequestPacket) -> Result<ResponsePacket, TransportError> {
+        let mut last_error = None;
+
+        for retry in 0..=self.policy.max_retries {
+            if retry > 0 {
+                tokio::time::sleep(self.policy.backoff(retry)).await;
+            }
+
+            for endpoint in self.candidates() {
+                match endpoint.send(request.clone(), &self.policy).await {
+                    Ok(response) => return Ok(response),
+                    Err(err) => last_error = Some(err),
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| TransportErrorKind::custom_str("no RPC endpoints")))
+    }
+
+    /// Healthy endpoints first, then the rest, each in the configured order.
+    fn candidates(&self) -> Vec<&Endpoint> {
+        let (healthy, unhealthy): (Vec<&Endpoint>, Vec<&Endpoint>) = self
+            .endpoints
+            .iter()
+            .partition(|endpoint| endpoint.healthy.load(Ordering::Relaxed));
+
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    /// Asks every endpoint for its latest block. Endpoints that do not answer,
+    /// or answer with a block too far behind the others, are marked
+    /// unhealthy; the rest are marked healthy.
+    async fn check_health(&self) {
+        let mut blocks = Vec::with_capacity(self.endpoints.len());
+        for endpoint in self.endpoints.iter() {
+            let block = tokio::time::timeout(
+                self.policy.request_timeout,
+                endpoint.probe.get_block_number(),
+            )
+            .await;
+
+            blocks.push(match block {
+                Ok(Ok(block)) => Some(block),
+                Ok(Err(err)) => {
+                    endpoint.mark_unhealthy(&endpoint.redact(&err));
+                    None
+                }
+                Err(_) => {
+                    endpoint.mark_unhealthy("health check timed out");
+                    None
+                }
+            });
+        }
+
+        let Some(highest) = blocks.iter().flatten().max().copied() else {
+            return;
+        };
+        for (endpoint, block) in self.endpoints.iter().zip(blocks) {
+            let Some(block) = block else {
+                continue;
+            };
+            endpoint.latest_block.store(block, Ordering::Relaxed);
+
+            let lag = highest - block;
+            if lag > self.policy.max_block_lag {
+                endpoint.mark_unhealthy(&format!("{} blocks behind", lag));
+            } else {
+                endpoint.mark_healthy();
+            }
+        }
+    }
+}
+
+impl Endpoint {
+    async fn send(
+        &self,
+        request: RequestPacket,
+        policy: &RetryPolicy,
+    ) -> Result<ResponsePacket, TransportError> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+
+        let mut transport = self.transport.clone();
+        let response =
+            match tokio::time::timeout(policy.request_timeout, transport.call(request)).await {
+                Ok(Ok(response)) => match response.as_error() {
+                    // Rate limits come back as error responses, but another
+                    // endpoint may well answer.
+                    Some(error) if error.is_retry_err() => {
+                        Err(TransportError::ErrorResp(error.clone()))
+                    }
+                    _ => Ok(response),
+                },
+                // Connection errors quote the URL; callers log them.
+                Ok(Err(err @ TransportError::Transport(TransportErrorKind::Custom(_)))) => {
+                    Err(TransportErrorKind::custom_str(&self.redact(&err)))
+                }
+                Ok(Err(err)) => Err(err),
+                Err(_) => {
+                    self.timeouts.fetch_add(1, Ordering::Relaxed);
+                    Err(TransportErrorKind::custom_str(&format!(
+                        "request timed out after {:?}",
+                        policy.request_timeout
+                    )))
+                }
+            };
+
+        match &response {
+            Ok(_) => {
+                self.latency_micros
+                    .fetch_add(elapsed_micros(started), Ordering::Relaxed);
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                self.mark_healthy();
+            }
+            Err(err) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                let reason = self.redact(err);
+                *self.last_error.lock().unwrap() = Some(reason.clone());
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= policy.unhealthy_after {
+                    self.mark_unhealthy(&reason);
+                }
+            }
+        }
+
+        response
+    }
+
+    fn mark_healthy(&self) {
+        if !self.healthy.swap(true, Ordering::Relaxed) {
+            println!("RPC endpoint {} is healthy again", self.host());
+        }
+    }
+
+    fn mark_unhealthy(&self, reason: &str) {
+        *self.last_error.lock().unwrap() = Some(reason.to_string());
+        if self.healthy.swap(false, Ordering::Relaxed) {
+            println!("RPC endpoint {} is unhealthy: {}", self.host(), reason);
+        }
+    }
+
+    /// Describes an error without the endpoint's URL. Providers take the API
+    /// key in the URL, and HTTP errors quote it in full.
+    fn redact(&self, err: &impl fmt::Display) -> String {
+        let message = err.to_string();
+        let url = self.url.as_str();
+
+        message
+            .replace(url, &self.host())
+            .replace(url.trim_end_matches('/'), &self.host())
+    }
+
+    fn host(&self) -> String {
+        self.url.host_str().unwrap_or_default().to_string()
+    }
+
+    fn stats(&self) -> EndpointStats {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let errors = self.errors.load(Ordering::Relaxed);
+        let successes = requests.saturating_sub(errors);
+        let latest_block = self.latest_block.load(Ordering::Relaxed);
+
+        EndpointStats {
+            endpoint: self.index,
+            host: self.host(),
+            healthy: self.healthy.load(Ordering::Relaxed),
+            requests,
+            errors,
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            error_rate: if requests == 0 {
+                0.0
+            } else {
+                errors as f64 / requests as f64
+            },
+            avg_latency_ms: if successes == 0 {
+                0.0
+            } else {
+                self.latency_micros.load(Ordering::Relaxed) as f64 / successes as f64 / 1000.0
+            },
+            latest_block: (latest_block > 0).then_some(latest_block),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+fn elapsed_micros(started: Instant) -> u64 {
+    u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX)
+}
+
+impl Service<RequestPacket> for RpcPool {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let pool = self.clone();
+        Box::pin(async move { pool.request(request).await })
+    }
+}
+
+/// Sends every request to one endpoint of a pool.
+#[derive(Clone)]
+struct Pinned {
+    pool: RpcPool,
+    index: usize,
+}
+
+impl Service<RequestPacket> for Pinned {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let pinned = self.clone();
+        Box::pin(async move {
+            let endpoint = &pinned.pool.endpoints[pinned.index];
+            endpoint.send(request, &pinned.pool.policy).await
+        })
+    }
+}
+
+impl fmt::Debug for RpcPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcPool")
+            .field(
+                "endpoints",
+                &self
+                    .endpoints
+                    .iter()
+                    .map(Endpoint::host)
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Health checks the endpoints of `pool` every health check interval. Runs
+/// for the lifetime of the server under [`crate::worker::supervise`], once
+/// per chain.
+pub async fn run_health_checks(pool: RpcPool) -> anyhow::Result<()> {
+    loop {
+        pool.check_health().await;
+        tokio::time::sleep(pool.policy.health_check_interval).await;
+    }
+}
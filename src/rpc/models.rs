@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::Serialize;
+use thiserror::Error;
+
+/// How requests to a chain's RPC endpoints are timed out, retried and
+/// health checked.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// How long a single request to one endpoint may take.
+    pub request_timeout: Duration,
+    /// How many more rounds over every endpoint are made once the first has
+    /// failed.
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Consecutive failures after which an endpoint is only tried once the
+    /// healthy ones have failed.
+    pub unhealthy_after: u32,
+    pub health_check_interval: Duration,
+    /// How far an endpoint may fall behind the highest block any endpoint
+    /// reports before it counts as unhealthy.
+    pub max_block_lag: u64,
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry round `retry`, counting from 1: doubled
+    /// every round up to `max_backoff`, of which a random half is taken off
+    /// so that requests failing together do not retry together.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+        let half = exponential / 2;
+
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// What an endpoint has done since the server started.
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStats {
+    /// Position in the configured list, which is also the order endpoints
+    /// are tried in.
+    pub endpoint: usize,
+    /// Only the host, since the rest of the URL usually carries an API key.
+    pub host: String,
+    pub healthy: bool,
+    pub requests: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    #[serde(rename = "errorRate")]
+    pub error_rate: f64,
+    /// Of successful requests.
+    #[serde(rename = "avgLatencyMs")]
+    pub avg_latency_ms: f64,
+    /// As of the last health check.
+    #[serde(rename = "latestBlock")]
+    pub latest_block: Option<u64>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainRpcStats {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    pub endpoints: Vec<EndpointStats>,
+}
+
+#[derive(Debug, Error)]
+pub enum RpcError {
+    #[error("no RPC URL configured")]
+    NoEndpoints,
+
+    #[error("invalid RPC URL {0}")]
+    InvalidUrl(String),
+}
@@ -0,0 +1,186 @@
+//! The tokens balances can be held in.
+//!
+//! Tokens are rows of the `tokens` table, one per chain and symbol. The token
+//! configured with `--token-address` and `--token-symbol` is always registered
+//! and enabled on the primary chain, and is the token of every record written
+//! before there was more than one. Other tokens are added to the table
+//! directly and picked up on the next start.
+//!
+//! Balances are kept per symbol, not per chain: a token with the same symbol
+//! on several chains is the same stablecoin, deposited on one chain and
+//! withdrawn on any other. What the platform holds is tracked per chain
+//! though, in the ledger's custody, and a chain only pays out withdrawals up
+//! to what it holds.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use alloy::primitives::Address;
+use models::{RegisteredToken, TokenError, TokenRecord};
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use crate::chain::registry::ChainRegistry;
+use crate::money::Token;
+
+pub mod models;
+
+#[derive(Debug, Clone)]
+pub struct TokenRegistry {
+    tokens: Arc<Vec<RegisteredToken>>,
+    default: Arc<RegisteredToken>,
+}
+
+impl TokenRegistry {
+    /// Registers `default` and loads every token of the enabled `chains`.
+    pub async fn load(
+        db: &Surreal<Any>,
+        chains: &ChainRegistry,
+        default: RegisteredToken,
+    ) -> Result<Self, TokenError> {
+        db.query(
+            "
+            UPSERT type::thing('tokens', [$chainId, $symbol]) SET
+                symbol = type::string($symbol),
+                address = type::string($address),
+                decimals = $decimals,
+                chainId = $chainId,
+                enabled = true;
+            ",
+        )
+        .bind(("chainId", default.chain_id))
+        .bind(("symbol", default.symbol.clone()))
+        .bind(("address", default.address.to_string()))
+        .bind(("decimals", default.decimals))
+        .await?
+        .check()?;
+
+        let chain_ids: Vec<u64> = chains.all().iter().map(|chain| chain.id()).collect();
+        let records = db
+            .query("SELECT symbol, address, decimals, chainId, enabled FROM tokens WHERE chainId IN $chainIds")
+            .bind(("chainIds", chain_ids))
+            .await?
+            .take::<Vec<TokenRecord>>(0)?;
+
+        let mut tokens = Vec::with_capacity(records.len());
+        for record in records {
+            let chain = chains.get(record.chain_id)?;
+            let address =
+                Address::from_str(&record.address).map_err(|_| TokenError::InvalidAddress {
+                    symbol: record.symbol.clone(),
+                    address: record.address.clone(),
+                })?;
+            let token = Token::load(&chain.provider, address, record.decimals).await?;
+
+            println!(
+                "Registered token {} at {} on chain {} with {} decimals{}",
+                record.symbol,
+                address,
+                record.chain_id,
+                token.decimals,
+                if record.enabled { "" } else { " (disabled)" }
+            );
+
+            tokens.push(RegisteredToken {
+                symbol: record.symbol,
+                address,
+                decimals: token.decimals,
+                chain_id: record.chain_id,
+                enabled: record.enabled,
+            });
+        }
+
+        Ok(TokenRegistry {
+            tokens: Arc::new(tokens),
+            default: Arc::new(default),
+        })
+    }
+
+    /// The configured token, which older records are in.
+    pub fn default_token(&self) -> &RegisteredToken {
+        &self.default
+    }
+
+    /// A token by its symbol, in any case, enabled on at least one chain.
+    pub fn get(&self, symbol: &str) -> Result<&RegisteredToken, TokenError> {
+        let token = self.registered(symbol)?;
+        self.enabled()
+            .find(|enabled| enabled.symbol == token.symbol)
+            .ok_or_else(|| TokenError::Disabled(token.symbol.clone()))
+    }
+
+    /// A token by its symbol, even if it has been disabled since. Used for
+    /// records that were already accepted in it.
+    pub fn registered(&self, symbol: &str) -> Result<&RegisteredToken, TokenError> {
+        self.tokens
+            .iter()
+            .find(|token| token.symbol.eq_ignore_ascii_case(symbol))
+            .ok_or_else(|| TokenError::Unknown(symbol.to_string()))
+    }
+
+    /// An enabled token by its symbol on `chain_id`.
+    pub fn get_on(&self, chain_id: u64, symbol: &str) -> Result<&RegisteredToken, TokenError> {
+        let token = self.registered_on(chain_id, symbol)?;
+        if !token.enabled {
+            return Err(TokenError::Disabled(token.symbol.clone()));
+        }
+
+        Ok(token)
+    }
+
+    /// A token by its symbol on `chain_id`, even if it has been disabled
+    /// since.
+    pub fn registered_on(
+        &self,
+        chain_id: u64,
+        symbol: &str,
+    ) -> Result<&RegisteredToken, TokenError> {
+        self.tokens
+            .iter()
+            .find(|token| token.chain_id == chain_id && token.symbol.eq_ignore_ascii_case(symbol))
+            .ok_or_else(|| TokenError::UnknownOnChain {
+                symbol: symbol.to_string(),
+                chain_id,
+            })
+    }
+
+    /// The enabled token with the contract at `address` on `chain_id`.
+    pub fn by_address(&self, chain_id: u64, address: Address) -> Option<&RegisteredToken> {
+        self.enabled()
+            .find(|token| token.chain_id == chain_id && token.address == address)
+    }
+
+    /// Every registered symbol, once, whichever chains it is on.
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols: Vec<&str> = Vec::new();
+        for token in self.tokens.iter() {
+            if !symbols.contains(&token.symbol.as_str()) {
+                symbols.push(&token.symbol);
+            }
+        }
+
+        symbols
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &RegisteredToken> {
+        self.tokens.iter().filter(|token| token.enabled)
+    }
+}
+
+/// Assigns the default token to ledger legs, journal entries, deposits and
+/// withdrawals written before records carried a token.
+pub async fn migrate_untokened(db: &Surreal<Any>, symbol: &str) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE ledger SET token = type::string($tokenSymbol) WHERE token = NONE;
+        UPDATE journal SET token = type::string($tokenSymbol) WHERE token = NONE;
+        UPDATE deposits SET token = type::string($tokenSymbol) WHERE token = NONE AND amount != NONE;
+        UPDATE withdrawals SET token = type::string($tokenSymbol) WHERE token = NONE;
+        ",
+    )
+    .bind(("tokenSymbol", symbol.to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
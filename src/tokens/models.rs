@@ -0,0 +1,84 @@
+use alloy::primitives::Address;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::chain::models::ChainError;
+use crate::money::models::MoneyError;
+use crate::money::Token;
+
+/// A row of the `tokens` table. `decimals` may be left out, in which case
+/// they are read from the contract when the registry is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenRecord {
+    pub symbol: String,
+    pub address: String,
+    pub decimals: Option<u8>,
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    pub enabled: bool,
+}
+
+/// A token balances can be held in.
+#[derive(Debug, Clone, Serialize)]
+pub struct RegisteredToken {
+    pub symbol: String,
+    pub address: Address,
+    pub decimals: u8,
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    pub enabled: bool,
+}
+
+impl RegisteredToken {
+    pub fn token(&self) -> Token {
+        Token {
+            address: self.address,
+            decimals: self.decimals,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("token {0} is not supported")]
+    Unknown(String),
+
+    #[error("token {0} is disabled")]
+    Disabled(String),
+
+    #[error("token {symbol} is not supported on chain {chain_id}")]
+    UnknownOnChain { symbol: String, chain_id: u64 },
+
+    #[error("token {symbol} has an invalid address {address}")]
+    InvalidAddress { symbol: String, address: String },
+
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<surrealdb::Error> for TokenError {
+    fn from(error: surrealdb::Error) -> Self {
+        TokenError::Surrealdb(Box::new(error))
+    }
+}
+
+impl TokenError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            TokenError::Unknown(_)
+            | TokenError::Disabled(_)
+            | TokenError::UnknownOnChain { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            TokenError::InvalidAddress { .. }
+            | TokenError::Money(_)
+            | TokenError::Chain(_)
+            | TokenError::Surrealdb(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
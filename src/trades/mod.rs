@@ -0,0 +1,646 @@
+//! The lifecycle of trades, the `transactions` that fill part of an offer.
+//!
+//! A trade is created `pending`, reserving part of the offer. The taker then
+//! pays the maker in fiat off-platform and marks the payment sent; the maker
+//! confirms receiving it, which releases the crypto from the maker's escrow
+//! to the taker and completes the trade. Until the payment is sent, the taker
+//! can cancel, and an unpaid trade expires. Once the payment is sent either
+//! party can dispute instead, leaving it to an admin to release or cancel.
+//!
+//! Every transition is a conditional update on the current status, so two
+//! requests racing on the same trade cannot both move it.
+
+use std::str::FromStr;
+
+use models::{Trade, TradeError, TradeStatus};
+use serde::Serialize;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::{Id, Thing};
+use surrealdb::Surreal;
+
+use crate::api::auth::models::{Claims, Role};
+use crate::api::private::models::CreateTransactionRequest;
+use crate::events::EventKind;
+use crate::ledger::models::{Account, EntryKind, JournalEntry, LedgerError};
+use crate::money::models::{Amount, MoneyError};
+use crate::{db, ledger, offers};
+
+pub mod models;
+
+/// Which side of a trade may take a step.
+#[derive(Debug, Clone, Copy)]
+enum Party {
+    Taker,
+    Maker,
+    Either,
+}
+
+/// The terms a new trade is reserved on.
+#[derive(Debug, Clone, Serialize)]
+struct Reservation {
+    id: String,
+    #[serde(rename = "offerId")]
+    offer_id: String,
+    #[serde(rename = "userId")]
+    user_id: String,
+    amount: Amount,
+    #[serde(rename = "cryptoType")]
+    crypto_type: String,
+    #[serde(rename = "pricePerUnit")]
+    price_per_unit: i128,
+    currency: String,
+    #[serde(rename = "takerFee")]
+    taker_fee: Amount,
+    #[serde(rename = "makerFee")]
+    maker_fee: Amount,
+    value: i128,
+    status: TradeStatus,
+    #[serde(rename = "randomTitle")]
+    random_title: String,
+}
+
+/// Opens a trade taking `amount` of an open offer, on the offer's terms. The
+/// taker fee is `taker_fee_bps` of the amount and is taken from the offer
+/// along with it; the maker fee and the value are the offer's, in proportion
+/// to the amount.
+///
+/// What is left of the offer is checked again and reserved in the same
+/// transaction the trade is created in, so concurrent takers can never
+/// reserve more than the offer has between them.
+pub async fn open(
+    db: &Surreal<Any>,
+    user_id: &str,
+    request: CreateTransactionRequest,
+    taker_fee_bps: u32,
+) -> Result<Trade, TradeError> {
+    let amount = request.amount.ensure_positive()?;
+    let offer = offers::terms(db, &request.offer_id).await?;
+
+    if offer.status != "open" {
+        return Err(TradeError::OfferNotOpen(offer.id.to_string()));
+    }
+    if offer.user_id.to_string() == user_id {
+        return Err(TradeError::OwnOffer(offer.id.to_string()));
+    }
+
+    let taker_fee = amount.checked_bps(taker_fee_bps)?;
+    let requested = amount.checked_add(taker_fee)?;
+    if requested > offer.remaining {
+        return Err(TradeError::InsufficientLiquidity {
+            requested,
+            remaining: offer.remaining.max(Amount::ZERO),
+        });
+    }
+
+    let reservation = Reservation {
+        id: Thing::from(("transactions", Id::ulid())).to_string(),
+        offer_id: offer.id.to_string(),
+        user_id: user_id.to_string(),
+        amount,
+        crypto_type: offer.crypto_type,
+        price_per_unit: offer.price_per_unit,
+        currency: offer.currency,
+        taker_fee,
+        maker_fee: Amount::from_base_units(pro_rata(offer.fee.base_units(), amount, offer.amount)?),
+        value: pro_rata(offer.value, amount, offer.amount)?,
+        status: TradeStatus::Pending,
+        random_title: request.random_title,
+    };
+
+    let mut attempt = 0;
+    let reserved = loop {
+        match reserve(db, &reservation).await {
+            Err(TradeError::Surrealdb(error))
+                if db::is_conflict(&error) && attempt < db::CONFLICT_RETRIES =>
+            {
+                attempt += 1;
+                println!(
+                    "Retrying trade on offer {} after a write conflict",
+                    reservation.offer_id
+                );
+                db::conflict_backoff(attempt).await;
+            }
+            result => break result,
+        }
+    };
+
+    match reserved {
+        Ok(()) => get(db, &reservation.id).await,
+        Err(TradeError::Surrealdb(error))
+            if error.to_string().contains("insufficient liquidity") =>
+        {
+            let offer = offers::terms(db, &reservation.offer_id).await?;
+            Err(TradeError::InsufficientLiquidity {
+                requested,
+                remaining: offer.remaining.max(Amount::ZERO),
+            })
+        }
+        Err(TradeError::Surrealdb(error)) if error.to_string().contains("offer is not open") => {
+            Err(TradeError::OfferNotOpen(reservation.offer_id))
+        }
+        // Other takers kept winning the offer. If they took what this trade
+        // wanted, say so; otherwise the taker can simply try again.
+        Err(TradeError::Surrealdb(error)) if db::is_conflict(&error) => {
+            let offer = offers::terms(db, &reservation.offer_id).await?;
+            if requested > offer.remaining {
+                return Err(TradeError::InsufficientLiquidity {
+                    requested,
+                    remaining: offer.remaining.max(Amount::ZERO),
+                });
+            }
+            Err(TradeError::OfferBusy(reservation.offer_id))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn reserve(db: &Surreal<Any>, reservation: &Reservation) -> Result<(), TradeError> {
+    // Bumping the offer makes two concurrent trades on it conflict, so only
+    // one of them can pass the checks below and commit. The maker fee is
+    // capped at what is left of the offer's fee, which rounding up could
+    // otherwise take the last trade past.
+    let mut response = db
+        .query(
+            "
+            BEGIN TRANSACTION;
+            LET $offer = (UPDATE ONLY type::thing($offerId) SET version = (version OR 0) + 1 RETURN AFTER);
+            IF $offer.status != 'open' {
+                THROW 'offer is not open';
+            };
+            LET $taken = math::sum(SELECT VALUE amount + takerFee FROM transactions
+                WHERE offerId = $offer.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now()));
+            LET $makerFees = math::sum(SELECT VALUE makerFee FROM transactions
+                WHERE offerId = $offer.id AND status NOT IN ['cancelled', 'expired']
+                AND (status != 'pending' OR expiresAt >= time::now()));
+            IF $offer.amount - $taken < $amount + $takerFee {
+                THROW 'insufficient liquidity';
+            };
+            CREATE ONLY type::thing($id) SET
+                offerId = $offer.id,
+                amount = type::number($amount),
+                cryptoType = type::string($cryptoType),
+                pricePerUnit = type::number($pricePerUnit),
+                currency = type::string($currency),
+                takerFee = type::number($takerFee),
+                makerFee = math::min([type::number($makerFee), math::max([0, $offer.fee - $makerFees])]),
+                value = type::number($value),
+                expiresAt = time::now() + 5m,
+                status = type::string($status),
+                createdAt = time::now(),
+                updatedAt = time::now(),
+                randomTitle = type::string($randomTitle),
+                userId = type::thing($userId);
+            COMMIT TRANSACTION;
+            ",
+        )
+        .bind(reservation.clone())
+        .await?;
+
+    match db::take_error(&mut response) {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+pub async fn get(db: &Surreal<Any>, id: &str) -> Result<Trade, TradeError> {
+    let id = match Thing::from_str(id) {
+        Ok(thing) if thing.tb == "transactions" => thing.to_string(),
+        _ => return Err(TradeError::NotFound(id.to_string())),
+    };
+
+    db.query("SELECT *, offerId.userId AS makerId FROM ONLY type::thing($id)")
+        .bind(("id", id.clone()))
+        .await?
+        .take::<Option<Trade>>(0)?
+        .ok_or(TradeError::NotFound(id))
+}
+
+/// Trades the user took or that fill one of their offers, newest first.
+pub async fn list_for_user(db: &Surreal<Any>, user_id: &str) -> Result<Vec<Trade>, TradeError> {
+    let trades = db
+        .query(
+            "
+            SELECT *, offerId.userId AS makerId FROM transactions
+            WHERE userId = type::thing($userId) OR offerId.userId = type::thing($userId)
+            ORDER BY createdAt DESC;
+            ",
+        )
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Vec<Trade>>(0)?;
+
+    Ok(trades)
+}
+
+/// Disputed trades waiting for an admin, oldest first.
+pub async fn disputed(db: &Surreal<Any>) -> Result<Vec<Trade>, TradeError> {
+    let trades = db
+        .query(
+            "
+            SELECT *, offerId.userId AS makerId FROM transactions
+            WHERE status = type::string($status)
+            ORDER BY disputedAt ASC;
+            ",
+        )
+        .bind(("status", TradeStatus::Disputed))
+        .await?
+        .take::<Vec<Trade>>(0)?;
+
+    Ok(trades)
+}
+
+/// The taker says they sent the fiat payment.
+pub async fn mark_payment_sent(
+    db: &Surreal<Any>,
+    trade: &Trade,
+    claims: &Claims,
+) -> Result<Trade, TradeError> {
+    let admin = require_party(trade, claims, Party::Taker)?;
+
+    transition(db, trade, TradeStatus::PaymentSent, admin).await
+}
+
+/// The maker received the fiat payment, so the crypto is released to the
+/// taker and the trade completed.
+pub async fn confirm_payment(
+    db: &Surreal<Any>,
+    trade: &Trade,
+    claims: &Claims,
+) -> Result<Trade, TradeError> {
+    let admin = require_party(trade, claims, Party::Maker)?;
+
+    // A confirmed trade whose settlement failed can be confirmed again.
+    let trade = if trade.status == TradeStatus::PaymentConfirmed {
+        trade.clone()
+    } else {
+        transition(db, trade, TradeStatus::PaymentConfirmed, admin).await?
+    };
+
+    settle(db, &trade, admin).await
+}
+
+/// The taker calls off a trade they have not paid for.
+pub async fn cancel(
+    db: &Surreal<Any>,
+    trade: &Trade,
+    claims: &Claims,
+) -> Result<Trade, TradeError> {
+    let admin = require_party(trade, claims, Party::Taker)?;
+
+    transition(db, trade, TradeStatus::Cancelled, admin).await
+}
+
+/// Either party disputes a trade whose payment was marked sent.
+pub async fn dispute(
+    db: &Surreal<Any>,
+    trade: &Trade,
+    claims: &Claims,
+) -> Result<Trade, TradeError> {
+    let admin = require_party(trade, claims, Party::Either)?;
+
+    transition(db, trade, TradeStatus::Disputed, admin).await
+}
+
+/// Settles a disputed trade, or with `release == false` cancels it so its
+/// amount goes back to the offer.
+pub async fn resolve(
+    db: &Surreal<Any>,
+    id: &str,
+    admin_id: &str,
+    release: bool,
+) -> Result<Trade, TradeError> {
+    let trade = get(db, id).await?;
+
+    if release && trade.status == TradeStatus::Disputed {
+        settle(db, &trade, Some(admin_id)).await
+    } else if release {
+        Err(TradeError::InvalidTransition {
+            id: trade.id.to_string(),
+            from: trade.status,
+            to: TradeStatus::Completed,
+        })
+    } else if trade.status == TradeStatus::Disputed {
+        transition(db, &trade, TradeStatus::Cancelled, Some(admin_id)).await
+    } else {
+        Err(TradeError::InvalidTransition {
+            id: trade.id.to_string(),
+            from: trade.status,
+            to: TradeStatus::Cancelled,
+        })
+    }
+}
+
+/// Expires pending trades whose taker did not pay in time, giving their part
+/// back to the offer, and returns them. Each gets an event.
+pub async fn expire_overdue(db: &Surreal<Any>) -> Result<Vec<Thing>, TradeError> {
+    let mut response = db
+        .query(
+            "
+            BEGIN TRANSACTION;
+            LET $expired = (UPDATE transactions SET
+                status = type::string($expired),
+                expiredAt = time::now(),
+                updatedAt = time::now()
+            WHERE status = type::string($pending) AND expiresAt < time::now()
+            RETURN VALUE id);
+            FOR $trade IN $expired {
+                CREATE events SET kind = type::string($eventKind), subject = $trade, createdAt = time::now();
+            };
+            RETURN $expired;
+            COMMIT TRANSACTION;
+            ",
+        )
+        .bind(("expired", TradeStatus::Expired))
+        .bind(("pending", TradeStatus::Pending))
+        .bind(("eventKind", EventKind::TradeExpired))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        return Err(error.into());
+    }
+    let expired = response.take::<Vec<Thing>>(0)?;
+
+    Ok(expired)
+}
+
+/// Renames the statuses trades had before there was a lifecycle.
+pub async fn migrate_legacy_statuses(db: &Surreal<Any>) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE transactions SET
+            status = type::string($expired),
+            expiredAt = expiresAt,
+            updatedAt = time::now()
+        WHERE status = 'rejected';
+        UPDATE transactions SET
+            status = type::string($completed),
+            completedAt = updatedAt OR createdAt,
+            updatedAt = time::now()
+        WHERE status = 'successful';
+        ",
+    )
+    .bind(("expired", TradeStatus::Expired))
+    .bind(("completed", TradeStatus::Completed))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Lets the given party through, and admins in their place. Returns the admin
+/// so the trade records who stepped in.
+fn require_party<'a>(
+    trade: &Trade,
+    claims: &'a Claims,
+    party: Party,
+) -> Result<Option<&'a str>, TradeError> {
+    if claims.role == Role::Admin {
+        return Ok(Some(&claims.sub));
+    }
+
+    let is_taker = trade.user_id.to_string() == claims.sub;
+    let is_maker = trade.maker_id.to_string() == claims.sub;
+
+    let (allowed, role) = match party {
+        Party::Taker => (is_taker, "taker"),
+        Party::Maker => (is_maker, "maker"),
+        Party::Either => (is_taker || is_maker, "taker or maker"),
+    };
+    if allowed {
+        Ok(None)
+    } else {
+        Err(TradeError::WrongParty {
+            id: trade.id.to_string(),
+            role,
+        })
+    }
+}
+
+/// Moves `trade` from the status it was read in to `to`, recording when.
+/// Fails if that is not an allowed step, if the trade moved on since it was
+/// read, or if the payment is marked sent after the trade expired.
+async fn transition(
+    db: &Surreal<Any>,
+    trade: &Trade,
+    to: TradeStatus,
+    resolved_by: Option<&str>,
+) -> Result<Trade, TradeError> {
+    let id = trade.id.to_string();
+    let invalid = |from| TradeError::InvalidTransition {
+        id: id.clone(),
+        from,
+        to,
+    };
+    if !trade.status.can_become(to) {
+        return Err(invalid(trade.status));
+    }
+
+    let moved = db
+        .query(format!(
+            "
+            UPDATE type::thing($id) SET
+                status = type::string($to),
+                {} = time::now(),
+                resolvedBy = IF $resolvedBy THEN type::thing($resolvedBy) ELSE resolvedBy END,
+                updatedAt = time::now()
+            WHERE status = type::string($from)
+                AND ($to != 'payment_sent' OR expiresAt >= time::now())
+            RETURN VALUE id;
+            ",
+            to.timestamp_field()
+        ))
+        .bind(("id", id.clone()))
+        .bind(("to", to))
+        .bind(("from", trade.status))
+        .bind(("resolvedBy", resolved_by.map(str::to_string)))
+        .await?
+        .take::<Option<Thing>>(0)?;
+
+    let current = get(db, &id).await?;
+    if moved.is_none() && current.status == trade.status && to == TradeStatus::PaymentSent {
+        return Err(TradeError::Overdue(id));
+    }
+    if moved.is_none() {
+        return Err(invalid(current.status));
+    }
+
+    println!("Trade {} moved from {} to {}", id, trade.status, to);
+
+    Ok(current)
+}
+
+/// Pays the taker out of the maker's escrow, takes both fees, and completes
+/// the trade. The journal entry is keyed by the trade, so settling a trade
+/// twice moves its funds once.
+async fn settle(
+    db: &Surreal<Any>,
+    trade: &Trade,
+    resolved_by: Option<&str>,
+) -> Result<Trade, TradeError> {
+    let token = db
+        .query("SELECT VALUE cryptoType FROM ONLY type::thing($offerId)")
+        .bind(("offerId", trade.offer_id.to_string()))
+        .await?
+        .take::<Option<String>>(0)?
+        .ok_or(TradeError::NotFound(trade.offer_id.to_string()))?;
+
+    let maker = trade.maker_id.to_string();
+    let mut entry = JournalEntry::new(EntryKind::TradeSettlement, &token, trade.id.to_string())
+        .transfer(
+            Account::Escrow(maker.clone()),
+            Account::Available(trade.user_id.to_string()),
+            trade.amount,
+        );
+    let fees = trade.taker_fee.checked_add(trade.maker_fee)?;
+    if fees.is_positive() {
+        entry = entry.transfer(Account::Escrow(maker), Account::Fees, fees);
+    }
+
+    match ledger::post(db, entry).await {
+        Ok(()) | Err(LedgerError::Duplicate { .. }) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    transition(db, trade, TradeStatus::Completed, resolved_by).await
+}
+
+/// `total` scaled by `part / whole`, rounded up.
+fn pro_rata(total: i128, part: Amount, whole: Amount) -> Result<i128, MoneyError> {
+    let whole = whole.ensure_positive()?.base_units();
+
+    total
+        .checked_mul(part.base_units())
+        .and_then(|scaled| scaled.checked_add(whole - 1))
+        .map(|scaled| scaled / whole)
+        .ok_or(MoneyError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use super::*;
+    use crate::api::private::models::CreateOfferRequest;
+
+    async fn memory_db() -> Surreal<Any> {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        db
+    }
+
+    /// A sell offer of 1000 USDT plus a 10 fee by `maker`, who is funded for it.
+    async fn funded_offer(db: &Surreal<Any>, maker: &str) -> String {
+        ledger::post(
+            db,
+            JournalEntry::new(EntryKind::Opening, "USDT", format!("opening:{}", maker)).transfer(
+                Account::Custody(1),
+                Account::Available(maker.to_string()),
+                Amount::from_base_units(1_010),
+            ),
+        )
+        .await
+        .unwrap();
+        offers::create(
+            db,
+            maker,
+            CreateOfferRequest {
+                offer_type: "sell".to_string(),
+                amount: Amount::from_base_units(1_000),
+                fee: Amount::from_base_units(10),
+                crypto_type: "USDT".to_string(),
+                currency: "EUR".to_string(),
+                price_per_unit: 1,
+                value: 1_000,
+                rev_tag: "maker".to_string(),
+            },
+        )
+        .await
+        .unwrap()
+        .to_string()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_takers_cannot_take_more_than_the_offer() {
+        let db = memory_db().await;
+        let offer_id = funded_offer(&db, "user:maker").await;
+
+        // Each takes 100 plus a 1% fee, so only nine of them fit. A taker
+        // turned away because the offer was busy tries again, so every one of
+        // them either gets a trade or finds the offer taken.
+        let takers = (0..20).map(|taker| {
+            let db = db.clone();
+            let offer_id = offer_id.clone();
+            tokio::spawn(async move {
+                loop {
+                    let request = CreateTransactionRequest {
+                        offer_id: offer_id.clone(),
+                        amount: Amount::from_base_units(100),
+                        random_title: format!("trade {}", taker),
+                    };
+                    match open(&db, &format!("user:taker{}", taker), request, 100).await {
+                        Err(TradeError::OfferBusy(_)) => tokio::task::yield_now().await,
+                        result => return result,
+                    }
+                }
+            })
+        });
+
+        let mut opened = 0;
+        for taker in takers {
+            match taker.await.unwrap() {
+                Ok(_) => opened += 1,
+                Err(TradeError::InsufficientLiquidity { .. }) => {}
+                Err(err) => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        let taken = db
+            .query(
+                "RETURN math::sum(SELECT VALUE amount + takerFee FROM transactions
+                WHERE offerId = type::thing($offerId))",
+            )
+            .bind(("offerId", offer_id.clone()))
+            .await
+            .unwrap()
+            .take::<Option<Amount>>(0)
+            .unwrap()
+            .unwrap_or_default();
+
+        assert_eq!(opened, 9);
+        assert_eq!(taken, Amount::from_base_units(909));
+    }
+
+    #[tokio::test]
+    async fn admins_may_step_in_for_either_party() {
+        let db = memory_db().await;
+        let offer_id = funded_offer(&db, "user:maker").await;
+        let request = CreateTransactionRequest {
+            offer_id,
+            amount: Amount::from_base_units(100),
+            random_title: "trade".to_string(),
+        };
+        let trade = open(&db, "user:taker", request, 0).await.unwrap();
+
+        let claims = |sub: &str, role| Claims {
+            exp: 0,
+            sub: sub.to_string(),
+            role,
+        };
+
+        assert!(matches!(
+            mark_payment_sent(&db, &trade, &claims("user:maker", Role::User)).await,
+            Err(TradeError::WrongParty { .. })
+        ));
+
+        let trade = mark_payment_sent(&db, &trade, &claims("user:admin", Role::Admin))
+            .await
+            .unwrap();
+        assert_eq!(trade.status, TradeStatus::PaymentSent);
+        assert_eq!(
+            trade.resolved_by.map(|admin| admin.to_string()),
+            Some("user:admin".to_string())
+        );
+    }
+}
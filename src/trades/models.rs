@@ -0,0 +1,222 @@
+use std::fmt;
+
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use surrealdb::sql::{Datetime, Thing};
+use thiserror::Error;
+
+use crate::ledger::models::LedgerError;
+use crate::money::models::{Amount, MoneyError};
+use crate::offers::models::OfferError;
+
+/// Where a trade is in its lifecycle.
+///
+/// ```text
+/// pending ──> payment_sent ──> payment_confirmed ──> completed
+///    │              │                                    ^
+///    ├──> cancelled └──> disputed ───────────────────────┤
+///    └──> expired             └──> cancelled             │
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStatus {
+    /// Reserved part of an offer, waiting for the taker to pay.
+    Pending,
+    /// The taker says they sent the fiat payment.
+    PaymentSent,
+    /// The maker received the payment; the crypto is being released.
+    PaymentConfirmed,
+    /// The crypto was released to the taker.
+    Completed,
+    /// Called off before any payment, by the taker or an admin.
+    Cancelled,
+    /// Not paid in time.
+    Expired,
+    /// Waiting for an admin to release or cancel it.
+    Disputed,
+}
+
+impl TradeStatus {
+    /// Whether a trade in this status may move to `next`.
+    pub fn can_become(self, next: TradeStatus) -> bool {
+        use TradeStatus::*;
+
+        matches!(
+            (self, next),
+            (Pending, PaymentSent | Cancelled | Expired)
+                | (PaymentSent, PaymentConfirmed | Disputed)
+                | (PaymentConfirmed, Completed)
+                | (Disputed, Completed | Cancelled)
+        )
+    }
+
+    /// The field that records when a trade reached this status.
+    pub fn timestamp_field(self) -> &'static str {
+        match self {
+            TradeStatus::Pending => "createdAt",
+            TradeStatus::PaymentSent => "paymentSentAt",
+            TradeStatus::PaymentConfirmed => "paymentConfirmedAt",
+            TradeStatus::Completed => "completedAt",
+            TradeStatus::Cancelled => "cancelledAt",
+            TradeStatus::Expired => "expiredAt",
+            TradeStatus::Disputed => "disputedAt",
+        }
+    }
+}
+
+impl fmt::Display for TradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            TradeStatus::Pending => "pending",
+            TradeStatus::PaymentSent => "payment_sent",
+            TradeStatus::PaymentConfirmed => "payment_confirmed",
+            TradeStatus::Completed => "completed",
+            TradeStatus::Cancelled => "cancelled",
+            TradeStatus::Expired => "expired",
+            TradeStatus::Disputed => "disputed",
+        };
+        f.write_str(status)
+    }
+}
+
+/// A row of the `transactions` table: a taker filling part of an offer.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde(rename = "offerId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub offer_id: Thing,
+    /// The taker.
+    #[serde(rename = "userId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub user_id: Thing,
+    /// Owner of the offer.
+    #[serde(rename = "makerId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub maker_id: Thing,
+    pub amount: Amount,
+    #[serde(rename = "cryptoType")]
+    pub crypto_type: String,
+    #[serde(rename = "pricePerUnit")]
+    pub price_per_unit: i128,
+    pub currency: String,
+    #[serde(rename = "takerFee")]
+    pub taker_fee: Amount,
+    #[serde(rename = "makerFee")]
+    pub maker_fee: Amount,
+    pub value: i128,
+    #[serde(rename = "randomTitle")]
+    pub random_title: String,
+    pub status: TradeStatus,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Datetime,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<Datetime>,
+    #[serde(rename = "paymentSentAt")]
+    pub payment_sent_at: Option<Datetime>,
+    #[serde(rename = "paymentConfirmedAt")]
+    pub payment_confirmed_at: Option<Datetime>,
+    #[serde(rename = "completedAt")]
+    pub completed_at: Option<Datetime>,
+    #[serde(rename = "cancelledAt")]
+    pub cancelled_at: Option<Datetime>,
+    #[serde(rename = "expiredAt")]
+    pub expired_at: Option<Datetime>,
+    #[serde(rename = "disputedAt")]
+    pub disputed_at: Option<Datetime>,
+    /// The admin who last stepped in, whether taking a party's step or
+    /// resolving a dispute.
+    #[serde(rename = "resolvedBy")]
+    #[serde_as(serialize_as = "Option<DisplayFromStr>")]
+    pub resolved_by: Option<Thing>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<Datetime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResolveTradeRequest {
+    /// Releases the crypto to the taker if `true`, cancels the trade if
+    /// `false`.
+    pub release: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum TradeError {
+    #[error("trade {0} not found")]
+    NotFound(String),
+
+    #[error("offer {0} is not open")]
+    OfferNotOpen(String),
+
+    #[error("offer {0} is busy, try again")]
+    OfferBusy(String),
+
+    #[error("offer {0} is your own")]
+    OwnOffer(String),
+
+    #[error("insufficient liquidity: the trade takes {requested}, but only {remaining} is left")]
+    InsufficientLiquidity {
+        requested: Amount,
+        remaining: Amount,
+    },
+
+    #[error("only the {role} of trade {id} can do that")]
+    WrongParty { id: String, role: &'static str },
+
+    #[error("trade {0} was not paid in time")]
+    Overdue(String),
+
+    #[error("trade {id} cannot go from {from} to {to}")]
+    InvalidTransition {
+        id: String,
+        from: TradeStatus,
+        to: TradeStatus,
+    },
+
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error(transparent)]
+    Offer(#[from] OfferError),
+
+    #[error(transparent)]
+    Ledger(#[from] Box<LedgerError>),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<surrealdb::Error> for TradeError {
+    fn from(error: surrealdb::Error) -> Self {
+        TradeError::Surrealdb(Box::new(error))
+    }
+}
+
+impl From<LedgerError> for TradeError {
+    fn from(error: LedgerError) -> Self {
+        TradeError::Ledger(Box::new(error))
+    }
+}
+
+impl TradeError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            TradeError::NotFound(_) => StatusCode::NOT_FOUND,
+            TradeError::WrongParty { .. } => StatusCode::FORBIDDEN,
+            TradeError::InvalidTransition { .. }
+            | TradeError::Overdue(_)
+            | TradeError::OfferNotOpen(_)
+            | TradeError::OfferBusy(_) => StatusCode::CONFLICT,
+            TradeError::OwnOffer(_) | TradeError::InsufficientLiquidity { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            TradeError::Money(error) => error.status_code(),
+            TradeError::Offer(error) => error.status_code(),
+            TradeError::Ledger(_) | TradeError::Surrealdb(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Wallet addresses linked to a user.
+//!
+//! A user can sign in with, and deposit from, any of their linked wallets.
+
+use models::{Wallet, WalletError};
+use surrealdb::engine::any::Any;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+use crate::db;
+
+pub mod models;
+
+/// Links `address` to a user. An address can only belong to one user.
+pub async fn link(db: &Surreal<Any>, user_id: &str, address: &str) -> Result<Wallet, WalletError> {
+    let address = address.to_lowercase();
+
+    let mut response = db
+        .query(
+            "
+            CREATE ONLY wallets SET
+                userId = type::thing($userId),
+                address = type::string($address),
+                createdAt = time::now();
+            ",
+        )
+        .bind(("userId", user_id.to_string()))
+        .bind(("address", address.clone()))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        if db::violates_index(&error, "wallets_address") {
+            return Err(WalletError::AlreadyLinked(address));
+        }
+        return Err(error.into());
+    }
+
+    response
+        .take::<Option<Wallet>>(0)?
+        .ok_or(WalletError::NotLinked(address))
+}
+
+pub async fn list_for_user(db: &Surreal<Any>, user_id: &str) -> Result<Vec<Wallet>, WalletError> {
+    let wallets = db
+        .query("SELECT * FROM wallets WHERE userId = type::thing($userId) ORDER BY createdAt ASC")
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Vec<Wallet>>(0)?;
+
+    Ok(wallets)
+}
+
+/// The user a wallet is linked to, if any.
+pub async fn owner(db: &Surreal<Any>, address: &str) -> Result<Option<Thing>, WalletError> {
+    let owner = db
+        .query("SELECT VALUE userId FROM wallets WHERE address = type::string($address)")
+        .bind(("address", address.to_lowercase()))
+        .await?
+        .take::<Option<Thing>>(0)?;
+
+    Ok(owner)
+}
+
+pub async fn is_linked(
+    db: &Surreal<Any>,
+    user_id: &str,
+    address: &str,
+) -> Result<bool, WalletError> {
+    let owner = owner(db, address).await?;
+
+    Ok(owner.is_some_and(|owner| owner.to_string() == user_id))
+}
+
+/// Links the address every existing user signed up with, for users created
+/// before wallets were tracked separately.
+pub async fn link_signup_addresses(db: &Surreal<Any>) -> Result<(), WalletError> {
+    db.query(
+        "
+        FOR $user IN (SELECT id, address FROM user WHERE address != NONE) {
+            IF COUNT(SELECT * FROM wallets WHERE address = $user.address) = 0 THEN {
+                CREATE wallets SET userId = $user.id, address = $user.address, createdAt = time::now();
+            } END;
+        };
+        ",
+    )
+    .await?
+    .check()?;
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use surrealdb::sql::{Datetime, Thing};
+use thiserror::Error;
+
+/// An address a user proved they control by signing in with it.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wallet {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde(rename = "userId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub user_id: Thing,
+    pub address: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+}
+
+#[derive(Debug, Error)]
+pub enum WalletError {
+    #[error("wallet {0} is already linked to an account")]
+    AlreadyLinked(String),
+
+    #[error("wallet {0} could not be linked")]
+    NotLinked(String),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<surrealdb::Error> for WalletError {
+    fn from(error: surrealdb::Error) -> Self {
+        WalletError::Surrealdb(Box::new(error))
+    }
+}
+
+impl WalletError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            WalletError::AlreadyLinked(_) => StatusCode::CONFLICT,
+            WalletError::NotLinked(_) | WalletError::Surrealdb(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Per-user allowlists of withdrawal addresses.
+//!
+//! The allowlist is opt-in: a user without any allowed addresses can withdraw
+//! anywhere. Adding an address takes a SIWE-signed confirmation from one of
+//! the user's linked wallets, and the address only becomes usable after a
+//! waiting period, so a stolen session cannot redirect withdrawals right away.
+//! Removing an address takes a signed confirmation too, since removing the
+//! last one lets the user withdraw anywhere again.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use super::models::{WithdrawalAddress, WithdrawalError};
+use crate::db;
+
+/// The SIWE statement a user signs to allow withdrawals to `address`.
+pub fn confirmation_statement(address: &str) -> String {
+    format!("Allow withdrawals to {}", address)
+}
+
+/// The SIWE statement a user signs to remove `address` from their allowlist.
+pub fn removal_statement(address: &str) -> String {
+    format!("Remove withdrawals to {}", address)
+}
+
+/// Adds `address` to the user's allowlist, usable after `waiting_secs`.
+pub async fn add(
+    db: &Surreal<Any>,
+    user_id: &str,
+    address: &str,
+    waiting_secs: u64,
+) -> Result<WithdrawalAddress, WithdrawalError> {
+    let mut response = db
+        .query(
+            "
+            CREATE ONLY withdrawal_addresses SET
+                userId = type::thing($userId),
+                address = type::string($address),
+                activeAt = time::now() + type::duration($waiting),
+                createdAt = time::now();
+            ",
+        )
+        .bind(("userId", user_id.to_string()))
+        .bind(("address", address.to_string()))
+        .bind(("waiting", format!("{}s", waiting_secs)))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        if db::violates_index(&error, "withdrawal_addresses_user_address") {
+            return Err(WithdrawalError::AddressAlreadyAllowed(address.to_string()));
+        }
+        return Err(error.into());
+    }
+
+    response
+        .take::<Option<WithdrawalAddress>>(0)?
+        .ok_or(WithdrawalError::NotRecorded)
+}
+
+/// Removes `address` from the user's allowlist and returns what it was.
+pub async fn remove(
+    db: &Surreal<Any>,
+    user_id: &str,
+    address: &str,
+) -> Result<WithdrawalAddress, WithdrawalError> {
+    db.query(
+        "
+        DELETE withdrawal_addresses
+            WHERE userId = type::thing($userId) AND address = type::string($address)
+            RETURN BEFORE;
+        ",
+    )
+    .bind(("userId", user_id.to_string()))
+    .bind(("address", address.to_string()))
+    .await?
+    .take::<Vec<WithdrawalAddress>>(0)?
+    .into_iter()
+    .next()
+    .ok_or_else(|| WithdrawalError::AddressNotListed(address.to_string()))
+}
+
+pub async fn list_for_user(
+    db: &Surreal<Any>,
+    user_id: &str,
+) -> Result<Vec<WithdrawalAddress>, surrealdb::Error> {
+    db.query(
+        "SELECT * FROM withdrawal_addresses WHERE userId = type::thing($userId) ORDER BY createdAt DESC",
+    )
+    .bind(("userId", user_id.to_string()))
+    .await?
+    .take::<Vec<WithdrawalAddress>>(0)
+}
+
+/// Fails unless the user may withdraw to `address` right now.
+pub async fn check(db: &Surreal<Any>, user_id: &str, address: &str) -> Result<(), WithdrawalError> {
+    let allowed = list_for_user(db, user_id).await?;
+    if allowed.is_empty() {
+        return Ok(());
+    }
+
+    let Some(entry) = allowed.iter().find(|entry| entry.address == address) else {
+        return Err(WithdrawalError::AddressNotAllowed(address.to_string()));
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or_default();
+    if entry.active_at.0.timestamp() > now {
+        return Err(WithdrawalError::AddressCoolingOff(address.to_string()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn removing_the_last_address_allows_any_again() {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        let (allowed, other) = ("0x0a", "0x0b");
+
+        add(&db, "user:alice", allowed, 0).await.unwrap();
+        assert!(matches!(
+            check(&db, "user:alice", other).await,
+            Err(WithdrawalError::AddressNotAllowed(_))
+        ));
+
+        let removed = remove(&db, "user:alice", allowed).await.unwrap();
+        assert_eq!(removed.address, allowed);
+        assert!(matches!(
+            remove(&db, "user:alice", allowed).await,
+            Err(WithdrawalError::AddressNotListed(_))
+        ));
+        check(&db, "user:alice", other).await.unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+//! What a withdrawal costs the user on top of the amount sent.
+//!
+//! The hot wallet pays gas for every transfer, so the platform charges a fee
+//! to cover it. The fee is debited together with the amount, held in custody
+//! while the withdrawal is in flight, and only moved to the fees account once
+//! the transfer confirms. Refunds return it along with the amount.
+
+use std::str::FromStr;
+
+use alloy::primitives::Address;
+
+use super::models::{FeePolicy, WithdrawalError, WithdrawalQuote};
+use crate::money::models::{Amount, MoneyError};
+use crate::tokens::models::RegisteredToken;
+use crate::AppState;
+
+const WEI_PER_ETH: u128 = 1_000_000_000_000_000_000;
+
+/// Works out the fee for sending `amount` of `token` to `address`, on the
+/// token's chain, under the configured policy.
+pub async fn quote(
+    state: &AppState,
+    token: &RegisteredToken,
+    address: &str,
+    amount: Amount,
+) -> Result<WithdrawalQuote, WithdrawalError> {
+    amount.ensure_positive()?;
+
+    let fee = match state.withdrawal_fee_policy {
+        FeePolicy::Flat(fee) => fee,
+        // Rounded up, so small withdrawals never go out for free.
+        FeePolicy::Percentage(bps) => amount.checked_bps(bps)?,
+        FeePolicy::Gas {
+            eth_price,
+            margin_percent,
+        } => {
+            if token.symbol != state.tokens.default_token().symbol
+                || token.chain_id != state.chains.primary().id()
+            {
+                return Err(WithdrawalError::FeeUnavailable(format!(
+                    "no gas price for {} on chain {}",
+                    token.symbol, token.chain_id
+                )));
+            }
+            let gas_cost = estimate_gas_cost(state, token, address, amount).await?;
+            gas_fee(gas_cost, eth_price, margin_percent).ok_or(MoneyError::Overflow)?
+        }
+    };
+
+    Ok(WithdrawalQuote {
+        chain_id: token.chain_id,
+        token: token.symbol.clone(),
+        amount,
+        fee,
+        total: amount.checked_add(fee)?,
+    })
+}
+
+/// What the transfer would cost the hot wallet right now, in wei, at the
+/// same capped fees the worker signs with.
+async fn estimate_gas_cost(
+    state: &AppState,
+    token: &RegisteredToken,
+    address: &str,
+    amount: Amount,
+) -> Result<u128, WithdrawalError> {
+    let to = Address::from_str(address)
+        .map_err(|_| WithdrawalError::InvalidAddress(address.to_string()))?;
+
+    let hot_wallet = &state.chains.get(token.chain_id)?.hot_wallet;
+    let gas = hot_wallet
+        .estimate_gas(super::transfer_request(to, amount, &token.token())?)
+        .await
+        .map_err(|err| WithdrawalError::FeeUnavailable(err.to_string()))?;
+    let fees = hot_wallet
+        .estimate_fees()
+        .await
+        .map_err(|err| WithdrawalError::FeeUnavailable(err.to_string()))?;
+    let max_fee_per_gas = fees.max_fee_per_gas.min(state.withdrawal_max_fee_per_gas);
+
+    Ok(u128::from(gas) * max_fee_per_gas)
+}
+
+/// Converts `gas_cost` in wei to balance units and adds the margin, rounding
+/// up.
+fn gas_fee(gas_cost: u128, eth_price: Amount, margin_percent: u32) -> Option<Amount> {
+    let eth_price = u128::try_from(eth_price.base_units()).ok()?;
+    let marked_up = gas_cost
+        .checked_mul(eth_price)?
+        .checked_mul(100 + u128::from(margin_percent))?;
+
+    i128::try_from(marked_up.div_ceil(WEI_PER_ETH * 100))
+        .ok()
+        .map(Amount::from_base_units)
+}
@@ -0,0 +1,930 @@
+//! Withdrawals queued by users and sent from the hot wallet.
+//!
+//! A request debits the user and records a withdrawal; the [`worker`] signs,
+//! broadcasts and confirms it, and refunds it if it can never be mined. The
+//! signed transaction is stored before it is broadcast, so a restart resumes
+//! with the same transaction instead of signing a second one. The user also
+//! pays a fee on top of the amount; see [`fees`].
+
+use std::str::FromStr;
+
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, U256};
+use alloy::rpc::types::TransactionRequest;
+use models::{Withdrawal, WithdrawalError, WithdrawalLimits, WithdrawalQuote, WithdrawalStatus};
+use serde::Serialize;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::{Id, Thing};
+use surrealdb::Surreal;
+
+use crate::api::private::IERC20;
+use crate::db;
+use crate::hot_wallet::SignedTx;
+use crate::ledger;
+use crate::ledger::models::{Account, EntryKind, JournalEntry, LedgerError};
+use crate::money::models::{Amount, MoneyError};
+use crate::money::Token;
+
+pub mod allowlist;
+pub mod fees;
+pub mod models;
+pub mod worker;
+
+/// Debits the quoted amount and fee from the user and queues the amount to be
+/// sent to `address` on the quoted chain, or holds it for approval if it is
+/// above the approval threshold. The user's own limits take the place of
+/// `defaults`; see [`limits_for`].
+pub async fn request(
+    db: &Surreal<Any>,
+    defaults: &WithdrawalLimits,
+    user_id: &str,
+    address: &str,
+    quote: &WithdrawalQuote,
+) -> Result<Withdrawal, WithdrawalError> {
+    let WithdrawalQuote {
+        chain_id,
+        token,
+        amount,
+        fee,
+        total,
+    } = quote;
+    amount.ensure_positive()?;
+    fee.ensure_not_negative()?;
+
+    allowlist::check(db, user_id, address).await?;
+    let limits = &limits_for(db, defaults, user_id).await?;
+    // Only saves debiting and refunding a request that is clearly over; the
+    // limits are enforced when the withdrawal is recorded.
+    check_limits(db, limits, user_id, token, *amount).await?;
+
+    let status = match limits.approval_threshold {
+        Some(threshold) if *amount > threshold => WithdrawalStatus::PendingApproval,
+        _ => WithdrawalStatus::Requested,
+    };
+
+    // The debit is posted first, so the worker never sees a withdrawal that
+    // was not paid for. The ledger checks and debits the balance atomically.
+    let id = Thing::from(("withdrawals", Id::ulid()));
+    let debit = ledger::post(
+        db,
+        JournalEntry::new(EntryKind::Withdrawal, token, id.to_string()).transfer(
+            Account::Available(user_id.to_string()),
+            Account::Custody(*chain_id),
+            *total,
+        ),
+    )
+    .await;
+
+    match debit {
+        Ok(()) => {}
+        Err(LedgerError::InsufficientFunds(_)) => return Err(WithdrawalError::InsufficientBalance),
+        Err(LedgerError::InsufficientCustody { chain_id, .. }) => {
+            return Err(WithdrawalError::InsufficientCustody {
+                token: token.clone(),
+                chain_id,
+            })
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut attempt = 0;
+    let created = loop {
+        match insert(db, limits, &id, user_id, address, quote, status).await {
+            Err(WithdrawalError::Surrealdb(error))
+                if db::is_conflict(&error) && attempt < db::CONFLICT_RETRIES =>
+            {
+                attempt += 1;
+                println!("Retrying withdrawal {} after a write conflict", id);
+                db::conflict_backoff(attempt).await;
+            }
+            result => break result,
+        }
+    };
+
+    match created {
+        Ok(Some(withdrawal)) => Ok(withdrawal),
+        // Without a record the worker would never send the funds, so give
+        // them back straight away.
+        result => {
+            ledger::post(
+                db,
+                JournalEntry::new(EntryKind::WithdrawalRefund, token, id.to_string()).transfer(
+                    Account::Custody(*chain_id),
+                    Account::Available(user_id.to_string()),
+                    *total,
+                ),
+            )
+            .await?;
+            result?;
+            Err(WithdrawalError::NotRecorded)
+        }
+    }
+}
+
+/// The limits that apply to the user: those an admin set for them, and
+/// `defaults` for the rest.
+pub async fn limits_for(
+    db: &Surreal<Any>,
+    defaults: &WithdrawalLimits,
+    user_id: &str,
+) -> Result<WithdrawalLimits, WithdrawalError> {
+    let own = db
+        .query(
+            "SELECT daily, monthly, approvalThreshold FROM ONLY type::thing('user_withdrawal_limits', $userId)",
+        )
+        .bind(("userId", user_id.to_string()))
+        .await?
+        .take::<Option<WithdrawalLimits>>(0)?
+        .unwrap_or_default();
+
+    Ok(own.or(defaults))
+}
+
+/// Sets the user's own limits, replacing any set before. A limit left `None`
+/// falls back to the default.
+pub async fn set_limits(
+    db: &Surreal<Any>,
+    user_id: &str,
+    limits: &WithdrawalLimits,
+    admin_id: &str,
+) -> Result<(), WithdrawalError> {
+    let user = match Thing::from_str(user_id) {
+        Ok(thing) if thing.tb == "user" => thing.to_string(),
+        _ => return Err(WithdrawalError::UserNotFound(user_id.to_string())),
+    };
+
+    let exists = db
+        .query("SELECT VALUE id FROM ONLY type::thing($userId)")
+        .bind(("userId", user.clone()))
+        .await?
+        .take::<Option<Thing>>(0)?
+        .is_some();
+    if !exists {
+        return Err(WithdrawalError::UserNotFound(user_id.to_string()));
+    }
+
+    db.query(
+        "
+        UPSERT type::thing('user_withdrawal_limits', $userId) SET
+            daily = $daily,
+            monthly = $monthly,
+            approvalThreshold = $approvalThreshold,
+            updatedBy = type::thing($adminId),
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("userId", user))
+    .bind(("daily", limits.daily))
+    .bind(("monthly", limits.monthly))
+    .bind(("approvalThreshold", limits.approval_threshold))
+    .bind(("adminId", admin_id.to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// A limit as the insert transaction checks it.
+#[derive(Debug, Clone, Serialize)]
+struct LimitWindow {
+    period: &'static str,
+    window: &'static str,
+    limit: Amount,
+}
+
+/// Records the withdrawal, checking it against the user's limits in the same
+/// transaction. Bumping a row per user and token makes two concurrent
+/// withdrawals of the same user conflict, so only one of them can pass the
+/// check and commit.
+async fn insert(
+    db: &Surreal<Any>,
+    limits: &WithdrawalLimits,
+    id: &Thing,
+    user_id: &str,
+    address: &str,
+    quote: &WithdrawalQuote,
+    status: WithdrawalStatus,
+) -> Result<Option<Withdrawal>, WithdrawalError> {
+    let windows: Vec<LimitWindow> = limit_windows(limits)
+        .map(|(period, window, limit)| LimitWindow {
+            period,
+            window,
+            limit,
+        })
+        .collect();
+
+    let mut response = db
+        .query(
+            "
+            BEGIN TRANSACTION;
+            UPSERT type::thing('withdrawal_limits', [$userId, $tokenSymbol])
+                SET version = (version OR 0) + 1;
+            FOR $window IN $windows {
+                IF math::sum(SELECT VALUE amount FROM withdrawals
+                    WHERE userId = type::thing($userId)
+                    AND token = type::string($tokenSymbol)
+                    AND status NOT IN $excluded
+                    AND createdAt > time::now() - type::duration($window.window))
+                    + $amount > $window.limit {
+                    THROW 'withdrawal limit exceeded: ' + $window.period;
+                };
+            };
+            CREATE ONLY type::thing($id) SET
+                userId = type::thing($userId),
+                address = type::string($address),
+                chainId = $chainId,
+                token = type::string($tokenSymbol),
+                amount = type::number($amount),
+                fee = type::number($fee),
+                status = type::string($status),
+                confirmations = 0,
+                createdAt = time::now(),
+                updatedAt = time::now();
+            COMMIT TRANSACTION;
+            ",
+        )
+        .bind(("id", id.to_string()))
+        .bind(("userId", user_id.to_string()))
+        .bind(("address", address.to_string()))
+        .bind(("chainId", quote.chain_id))
+        .bind(("tokenSymbol", quote.token.clone()))
+        .bind(("amount", quote.amount))
+        .bind(("fee", quote.fee))
+        .bind(("status", status))
+        .bind(("windows", windows.clone()))
+        .bind((
+            "excluded",
+            vec![WithdrawalStatus::Failed, WithdrawalStatus::Refunded],
+        ))
+        .await?;
+
+    if let Some(error) = db::take_error(&mut response) {
+        let exceeded = windows.into_iter().find(|window| {
+            error
+                .to_string()
+                .contains(&format!("exceeded: {}", window.period))
+        });
+        return Err(match exceeded {
+            Some(window) => WithdrawalError::LimitExceeded {
+                period: window.period,
+                limit: window.limit,
+            },
+            None => error.into(),
+        });
+    }
+
+    let withdrawal = db
+        .query("SELECT * FROM ONLY type::thing($id)")
+        .bind(("id", id.to_string()))
+        .await?
+        .take::<Option<Withdrawal>>(0)?;
+
+    Ok(withdrawal)
+}
+
+/// The limits that are set, with the period they are named after and the
+/// window they are summed over.
+fn limit_windows(
+    limits: &WithdrawalLimits,
+) -> impl Iterator<Item = (&'static str, &'static str, Amount)> {
+    [
+        ("daily", "1d", limits.daily),
+        ("monthly", "30d", limits.monthly),
+    ]
+    .into_iter()
+    .filter_map(|(period, window, limit)| limit.map(|limit| (period, window, limit)))
+}
+
+/// Fails if `amount` would take the user over their daily or monthly limit in
+/// `token`. Withdrawals of other tokens do not count.
+/// Everything not failed or refunded counts, including withdrawals still
+/// waiting for approval.
+async fn check_limits(
+    db: &Surreal<Any>,
+    limits: &WithdrawalLimits,
+    user_id: &str,
+    token: &str,
+    amount: Amount,
+) -> Result<(), WithdrawalError> {
+    for (period, window, limit) in limit_windows(limits) {
+        let withdrawn = db
+            .query(
+                "
+                RETURN math::sum(SELECT VALUE amount FROM withdrawals
+                    WHERE userId = type::thing($userId)
+                    AND token = type::string($tokenSymbol)
+                    AND status NOT IN $excluded
+                    AND createdAt > time::now() - type::duration($window));
+                ",
+            )
+            .bind(("userId", user_id.to_string()))
+            .bind(("tokenSymbol", token.to_string()))
+            .bind((
+                "excluded",
+                vec![WithdrawalStatus::Failed, WithdrawalStatus::Refunded],
+            ))
+            .bind(("window", window))
+            .await?
+            .take::<Option<Amount>>(0)?
+            .unwrap_or_default();
+
+        if withdrawn.checked_add(amount)? > limit {
+            return Err(WithdrawalError::LimitExceeded { period, limit });
+        }
+    }
+
+    Ok(())
+}
+
+/// An ERC-20 transfer of `amount` to `to`.
+pub fn transfer_request(
+    to: Address,
+    amount: Amount,
+    token: &Token,
+) -> Result<TransactionRequest, MoneyError> {
+    let call = IERC20::transferCall {
+        to,
+        amount: token.to_token_units(amount)?,
+    };
+
+    Ok(TransactionRequest::default()
+        .with_to(token.address)
+        .with_value(U256::ZERO)
+        .with_call(&call))
+}
+
+pub async fn list_for_user(
+    db: &Surreal<Any>,
+    user_id: &str,
+) -> Result<Vec<Withdrawal>, surrealdb::Error> {
+    db.query(
+        "SELECT * FROM withdrawals WHERE userId = type::thing($userId) ORDER BY createdAt DESC",
+    )
+    .bind(("userId", user_id.to_string()))
+    .await?
+    .take::<Vec<Withdrawal>>(0)
+}
+
+/// Withdrawals held for admin approval, oldest first.
+pub async fn pending_approval(db: &Surreal<Any>) -> Result<Vec<Withdrawal>, surrealdb::Error> {
+    db.query(
+        "SELECT * FROM withdrawals WHERE status = type::string($status) ORDER BY createdAt ASC",
+    )
+    .bind(("status", WithdrawalStatus::PendingApproval))
+    .await?
+    .take::<Vec<Withdrawal>>(0)
+}
+
+/// Releases a held withdrawal to the worker, or with `approve == false`
+/// fails it so the worker refunds it.
+pub async fn review(
+    db: &Surreal<Any>,
+    id: &str,
+    admin_id: &str,
+    approve: bool,
+) -> Result<Withdrawal, WithdrawalError> {
+    let id = match Thing::from_str(id) {
+        Ok(thing) if thing.tb == "withdrawals" => thing.to_string(),
+        _ => return Err(WithdrawalError::NotFound(id.to_string())),
+    };
+
+    let (status, error) = if approve {
+        (WithdrawalStatus::Requested, None)
+    } else {
+        (WithdrawalStatus::Failed, Some("Rejected by an admin"))
+    };
+
+    let reviewed = db
+        .query(
+            "
+            UPDATE type::thing($id) SET
+                status = type::string($status),
+                error = $error,
+                reviewedBy = type::thing($adminId),
+                updatedAt = time::now()
+            WHERE status = type::string($pendingApproval)
+            RETURN AFTER;
+            ",
+        )
+        .bind(("id", id.clone()))
+        .bind(("status", status))
+        .bind(("error", error))
+        .bind(("adminId", admin_id.to_string()))
+        .bind(("pendingApproval", WithdrawalStatus::PendingApproval))
+        .await?
+        .take::<Option<Withdrawal>>(0)?;
+
+    if let Some(withdrawal) = reviewed {
+        return Ok(withdrawal);
+    }
+
+    let exists = db
+        .query("SELECT VALUE id FROM ONLY type::thing($id)")
+        .bind(("id", id.clone()))
+        .await?
+        .take::<Option<Thing>>(0)?
+        .is_some();
+
+    Err(if exists {
+        WithdrawalError::NotPendingApproval(id)
+    } else {
+        WithdrawalError::NotFound(id)
+    })
+}
+
+/// Withdrawals on `chain_id` the worker still has to look at, oldest first.
+pub async fn pending(
+    db: &Surreal<Any>,
+    chain_id: u64,
+) -> Result<Vec<Withdrawal>, surrealdb::Error> {
+    db.query(
+        "SELECT * FROM withdrawals WHERE chainId = $chainId AND status IN $statuses ORDER BY createdAt ASC",
+    )
+    .bind(("chainId", chain_id))
+        .bind((
+            "statuses",
+            vec![
+                WithdrawalStatus::Requested,
+                WithdrawalStatus::Signed,
+                WithdrawalStatus::Broadcast,
+                WithdrawalStatus::Failed,
+            ],
+        ))
+        .await?
+        .take::<Vec<Withdrawal>>(0)
+}
+
+/// Stores the signed transaction of a withdrawal and advances the hot
+/// wallet's persisted nonce on the withdrawal's chain past it, in one
+/// transaction.
+pub async fn mark_signed(
+    db: &Surreal<Any>,
+    withdrawal: &Withdrawal,
+    hot_wallet: Address,
+    signed: &SignedTx,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        BEGIN TRANSACTION;
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            txHash = type::string($txHash),
+            txHashes = [type::string($txHash)],
+            rawTx = type::string($rawTx),
+            nonce = $nonce,
+            maxFeePerGas = $maxFeePerGas,
+            maxPriorityFeePerGas = $maxPriorityFeePerGas,
+            signedAt = time::now(),
+            updatedAt = time::now();
+        UPSERT type::thing('nonces', [$chainId, $hotWallet]) SET
+            next = math::max([next OR 0, $nonce + 1]),
+            updatedAt = time::now();
+        COMMIT TRANSACTION;
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("status", WithdrawalStatus::Signed))
+    .bind(("txHash", signed.hash.to_string()))
+    .bind(("rawTx", signed.raw.to_string()))
+    .bind(("nonce", signed.nonce))
+    .bind(("maxFeePerGas", signed.fees.max_fee_per_gas))
+    .bind(("maxPriorityFeePerGas", signed.fees.max_priority_fee_per_gas))
+    .bind(("chainId", withdrawal.chain_id))
+    .bind(("hotWallet", hot_wallet.to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Stores a fee-bumped replacement for a withdrawal's stuck transaction. The
+/// hashes it replaces are kept, since any of them may still be mined.
+pub async fn mark_replaced(
+    db: &Surreal<Any>,
+    withdrawal: &Withdrawal,
+    signed: &SignedTx,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            txHash = type::string($txHash),
+            txHashes = array::union(txHashes OR [], [type::string($txHash)]),
+            rawTx = type::string($rawTx),
+            maxFeePerGas = $maxFeePerGas,
+            maxPriorityFeePerGas = $maxPriorityFeePerGas,
+            signedAt = time::now(),
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("txHash", signed.hash.to_string()))
+    .bind(("rawTx", signed.raw.to_string()))
+    .bind(("maxFeePerGas", signed.fees.max_fee_per_gas))
+    .bind(("maxPriorityFeePerGas", signed.fees.max_priority_fee_per_gas))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Puts a withdrawal whose signed transaction was never accepted back in the
+/// queue, to be signed again with a fresh nonce.
+pub async fn resign(db: &Surreal<Any>, withdrawal: &Withdrawal) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            txHash = NONE,
+            txHashes = [],
+            rawTx = NONE,
+            nonce = NONE,
+            maxFeePerGas = NONE,
+            maxPriorityFeePerGas = NONE,
+            signedAt = NONE,
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("status", WithdrawalStatus::Requested))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+pub async fn mark_broadcast(
+    db: &Surreal<Any>,
+    withdrawal: &Withdrawal,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("status", WithdrawalStatus::Broadcast))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Records which of a withdrawal's transactions was mined and in what
+/// block, or clears the block with `None` when it was reorged out.
+pub async fn mark_mined(
+    db: &Surreal<Any>,
+    withdrawal: &Withdrawal,
+    tx_hash: &str,
+    block: Option<(u64, &str)>,
+    confirmations: u64,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            txHash = type::string($txHash),
+            blockNumber = $blockNumber,
+            blockHash = $blockHash,
+            confirmations = $confirmations,
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("txHash", tx_hash.to_string()))
+    .bind(("blockNumber", block.map(|(number, _)| number)))
+    .bind(("blockHash", block.map(|(_, hash)| hash.to_string())))
+    .bind(("confirmations", confirmations))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Records the block at which the hot wallet's nonce was first seen past the
+/// withdrawal's nonce with none of its transactions mined, or clears it once
+/// the nonce is free again after a reorg.
+pub async fn mark_nonce_used(
+    db: &Surreal<Any>,
+    withdrawal: &Withdrawal,
+    block_number: Option<u64>,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            nonceUsedAt = $blockNumber,
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("blockNumber", block_number))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Marks a withdrawal confirmed and records the gas its transaction used.
+/// The fee held in custody is only now moved to the fees account.
+pub async fn mark_confirmed(
+    db: &Surreal<Any>,
+    withdrawal: &Withdrawal,
+    tx_hash: &str,
+    block_number: u64,
+    block_hash: &str,
+    confirmations: u64,
+    (gas_used, effective_gas_price): (u64, u128),
+) -> Result<(), WithdrawalError> {
+    if withdrawal.fee.is_positive() {
+        let fee = ledger::post(
+            db,
+            JournalEntry::new(EntryKind::Fee, &withdrawal.token, withdrawal.id.to_string())
+                .transfer(
+                    Account::Custody(withdrawal.chain_id),
+                    Account::Fees,
+                    withdrawal.fee,
+                ),
+        )
+        .await;
+
+        match fee {
+            Ok(()) | Err(LedgerError::Duplicate { .. }) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            txHash = type::string($txHash),
+            blockNumber = $blockNumber,
+            blockHash = type::string($blockHash),
+            confirmations = $confirmations,
+            gasUsed = $gasUsed,
+            effectiveGasPrice = $effectiveGasPrice,
+            gasCost = $gasCost,
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("status", WithdrawalStatus::Confirmed))
+    .bind(("txHash", tx_hash.to_string()))
+    .bind(("blockNumber", block_number))
+    .bind(("blockHash", block_hash.to_string()))
+    .bind(("confirmations", confirmations))
+    .bind(("gasUsed", gas_used))
+    .bind(("effectiveGasPrice", effective_gas_price))
+    .bind(("gasCost", u128::from(gas_used) * effective_gas_price))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+pub async fn mark_failed(
+    db: &Surreal<Any>,
+    withdrawal: &Withdrawal,
+    error: &str,
+) -> Result<(), surrealdb::Error> {
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            error = type::string($error),
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("status", WithdrawalStatus::Failed))
+    .bind(("error", error.to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Returns a failed withdrawal, fee included, to the user's available
+/// balance. The refund is referenced by the withdrawal, so it is posted at
+/// most once.
+pub async fn refund(db: &Surreal<Any>, withdrawal: &Withdrawal) -> Result<(), WithdrawalError> {
+    let refund = ledger::post(
+        db,
+        JournalEntry::new(
+            EntryKind::WithdrawalRefund,
+            &withdrawal.token,
+            withdrawal.id.to_string(),
+        )
+        .transfer(
+            Account::Custody(withdrawal.chain_id),
+            Account::Available(withdrawal.user_id.to_string()),
+            withdrawal.amount.checked_add(withdrawal.fee)?,
+        ),
+    )
+    .await;
+
+    match refund {
+        Ok(()) | Err(LedgerError::Duplicate { .. }) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    db.query(
+        "
+        UPDATE type::thing($id) SET
+            status = type::string($status),
+            updatedAt = time::now();
+        ",
+    )
+    .bind(("id", withdrawal.id.to_string()))
+    .bind(("status", WithdrawalStatus::Refunded))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use super::*;
+
+    async fn memory_db() -> Surreal<Any> {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db::define_schema(&db).await.unwrap();
+        db
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_requests_cannot_exceed_the_daily_limit() {
+        let db = memory_db().await;
+        let user_id = "user:alice";
+        ledger::post(
+            &db,
+            JournalEntry::new(EntryKind::Opening, "USDT", "opening:user:alice").transfer(
+                Account::Custody(1),
+                Account::Available(user_id.to_string()),
+                Amount::from_base_units(1_000),
+            ),
+        )
+        .await
+        .unwrap();
+        let limits = WithdrawalLimits {
+            daily: Some(Amount::from_base_units(300)),
+            ..Default::default()
+        };
+        let quote = WithdrawalQuote {
+            chain_id: 1,
+            token: "USDT".to_string(),
+            amount: Amount::from_base_units(100),
+            fee: Amount::ZERO,
+            total: Amount::from_base_units(100),
+        };
+
+        let requests = (0..6).map(|_| {
+            let (db, limits, quote) = (db.clone(), limits.clone(), quote.clone());
+            tokio::spawn(async move {
+                request(&db, &limits, user_id, &Address::ZERO.to_string(), &quote).await
+            })
+        });
+
+        let mut requested = 0;
+        for result in requests {
+            match result.await.unwrap() {
+                Ok(_) => requested += 1,
+                Err(WithdrawalError::LimitExceeded {
+                    period: "daily", ..
+                }) => {}
+                Err(err) => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        assert_eq!(requested, 3);
+        assert_eq!(
+            ledger::available_balance(&db, user_id, "USDT")
+                .await
+                .unwrap(),
+            Amount::from_base_units(700)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_users_own_limits_replace_the_defaults_per_token() {
+        let db = memory_db().await;
+        db.query("CREATE user:alice; CREATE user:bob;")
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+        for token in ["USDT", "USDC"] {
+            ledger::post(
+                &db,
+                JournalEntry::new(EntryKind::Opening, token, format!("opening:{}", token))
+                    .transfer(
+                        Account::Custody(1),
+                        Account::Available("user:alice".to_string()),
+                        Amount::from_base_units(1_000),
+                    ),
+            )
+            .await
+            .unwrap();
+        }
+        let defaults = WithdrawalLimits {
+            daily: Some(Amount::from_base_units(300)),
+            monthly: Some(Amount::from_base_units(1_000)),
+            ..Default::default()
+        };
+        let own = WithdrawalLimits {
+            daily: Some(Amount::from_base_units(100)),
+            ..Default::default()
+        };
+        set_limits(&db, "user:alice", &own, "user:admin")
+            .await
+            .unwrap();
+        assert!(matches!(
+            set_limits(&db, "user:nobody", &own, "user:admin").await,
+            Err(WithdrawalError::UserNotFound(_))
+        ));
+
+        let alice = limits_for(&db, &defaults, "user:alice").await.unwrap();
+        assert_eq!(alice.daily, Some(Amount::from_base_units(100)));
+        assert_eq!(alice.monthly, Some(Amount::from_base_units(1_000)));
+        let bob = limits_for(&db, &defaults, "user:bob").await.unwrap();
+        assert_eq!(bob.daily, Some(Amount::from_base_units(300)));
+
+        let quote = |token: &str, amount| WithdrawalQuote {
+            chain_id: 1,
+            token: token.to_string(),
+            amount: Amount::from_base_units(amount),
+            fee: Amount::ZERO,
+            total: Amount::from_base_units(amount),
+        };
+        let address = Address::ZERO.to_string();
+        assert!(matches!(
+            request(&db, &defaults, "user:alice", &address, &quote("USDT", 150)).await,
+            Err(WithdrawalError::LimitExceeded { period: "daily", limit })
+                if limit == Amount::from_base_units(100)
+        ));
+        // Each token has a daily limit of its own.
+        request(&db, &defaults, "user:alice", &address, &quote("USDT", 100))
+            .await
+            .unwrap();
+        request(&db, &defaults, "user:alice", &address, &quote("USDC", 100))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_chain_only_pays_out_what_it_holds() {
+        let db = memory_db().await;
+        for user_id in ["user:alice", "user:bob"] {
+            ledger::post(
+                &db,
+                JournalEntry::new(EntryKind::Deposit, "USDT", format!("deposit:{}", user_id))
+                    .transfer(
+                        Account::Custody(1),
+                        Account::Available(user_id.to_string()),
+                        Amount::from_base_units(500),
+                    ),
+            )
+            .await
+            .unwrap();
+        }
+        let quote = |chain_id, amount| WithdrawalQuote {
+            chain_id,
+            token: "USDT".to_string(),
+            amount: Amount::from_base_units(amount),
+            fee: Amount::ZERO,
+            total: Amount::from_base_units(amount),
+        };
+        let limits = WithdrawalLimits::default();
+        let address = Address::ZERO.to_string();
+
+        // Nothing was ever deposited on chain 2.
+        assert!(matches!(
+            request(&db, &limits, "user:alice", &address, &quote(2, 100)).await,
+            Err(WithdrawalError::InsufficientCustody { chain_id: 2, .. })
+        ));
+
+        request(&db, &limits, "user:alice", &address, &quote(1, 500))
+            .await
+            .unwrap();
+        request(&db, &limits, "user:bob", &address, &quote(1, 500))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ledger::balance(&db, &Account::Custody(1), "USDT")
+                .await
+                .unwrap(),
+            Amount::ZERO
+        );
+        assert_eq!(
+            ledger::available_balance(&db, "user:alice", "USDT")
+                .await
+                .unwrap(),
+            Amount::ZERO
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use surrealdb::sql::{Datetime, Thing};
+use thiserror::Error;
+
+use crate::chain::models::ChainError;
+use crate::ledger::models::LedgerError;
+use crate::money::models::{Amount, MoneyError};
+use crate::tokens::models::TokenError;
+
+/// Where a withdrawal is in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WithdrawalStatus {
+    /// Debited from the user, but above the approval threshold; waiting for
+    /// an admin to approve or reject it.
+    PendingApproval,
+    /// Debited from the user, waiting to be signed.
+    Requested,
+    /// Signed, but not yet accepted by the node.
+    Signed,
+    /// Accepted by the node, waiting for confirmations.
+    Broadcast,
+    Confirmed,
+    /// Can no longer be sent or was reverted; waiting for the refund.
+    Failed,
+    /// Failed and returned to the user's balance.
+    Refunded,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde(rename = "userId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub user_id: Thing,
+    pub address: String,
+    /// The chain the withdrawal is sent on.
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    /// Symbol of the token withdrawn.
+    pub token: String,
+    pub amount: Amount,
+    /// Charged on top of `amount`, and only kept once the withdrawal confirms.
+    #[serde(default)]
+    pub fee: Amount,
+    pub status: WithdrawalStatus,
+    #[serde(rename = "txHash")]
+    pub tx_hash: Option<String>,
+    /// Every transaction signed for this withdrawal, the original first and
+    /// then each fee-bumped replacement. Any one of them may be mined.
+    #[serde(rename = "txHashes", default)]
+    pub tx_hashes: Vec<String>,
+    #[serde(rename = "rawTx", skip_serializing)]
+    pub raw_tx: Option<String>,
+    pub nonce: Option<u64>,
+    #[serde(rename = "maxFeePerGas")]
+    pub max_fee_per_gas: Option<u128>,
+    #[serde(rename = "maxPriorityFeePerGas")]
+    pub max_priority_fee_per_gas: Option<u128>,
+    /// When the current transaction was signed.
+    #[serde(rename = "signedAt")]
+    pub signed_at: Option<Datetime>,
+    #[serde(rename = "blockNumber")]
+    pub block_number: Option<u64>,
+    #[serde(rename = "blockHash")]
+    pub block_hash: Option<String>,
+    #[serde(default)]
+    pub confirmations: u64,
+    /// The block at which another transaction was first seen using this
+    /// withdrawal's nonce, while none of its own transactions were mined.
+    #[serde(rename = "nonceUsedAt")]
+    pub nonce_used_at: Option<u64>,
+    /// Gas the confirmed transaction used, from its receipt.
+    #[serde(rename = "gasUsed")]
+    pub gas_used: Option<u64>,
+    #[serde(rename = "effectiveGasPrice")]
+    pub effective_gas_price: Option<u128>,
+    /// What the hot wallet paid for the confirmed transaction, in wei.
+    #[serde(rename = "gasCost")]
+    pub gas_cost: Option<u128>,
+    pub error: Option<String>,
+    /// The admin who approved or rejected a withdrawal held for approval.
+    #[serde(rename = "reviewedBy")]
+    #[serde_as(serialize_as = "Option<DisplayFromStr>")]
+    pub reviewed_by: Option<Thing>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Datetime,
+}
+
+/// How the fee charged on top of a withdrawal is worked out. Fees are in the
+/// token withdrawn.
+#[derive(Debug, Clone)]
+pub enum FeePolicy {
+    Flat(Amount),
+    /// In basis points of the amount.
+    Percentage(u32),
+    /// The gas a transfer is estimated to cost at current prices, converted
+    /// with `eth_price` and marked up by `margin_percent`. The price is in
+    /// the default token and of the primary chain's gas, so only withdrawals
+    /// of the default token on the primary chain can be quoted.
+    Gas {
+        eth_price: Amount,
+        margin_percent: u32,
+    },
+}
+
+/// What a withdrawal would cost right now.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawalQuote {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    pub token: String,
+    pub amount: Amount,
+    pub fee: Amount,
+    /// What is debited from the balance.
+    pub total: Amount,
+}
+
+/// Caps on how much a user can withdraw. Amounts are in the same units as
+/// balances; `None` means no limit.
+///
+/// Limits apply to each token on its own: amounts of different tokens are
+/// not worth the same and are never added up, so a daily limit of 1,000
+/// allows 1,000 USDT and 1,000 USDC on the same day.
+///
+/// The server-wide limits are the defaults. An admin can set any of them
+/// for a single user, and those take their place.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WithdrawalLimits {
+    /// Over any rolling 24 hours.
+    #[serde(default)]
+    pub daily: Option<Amount>,
+    /// Over any rolling 30 days.
+    #[serde(default)]
+    pub monthly: Option<Amount>,
+    /// Withdrawals above this are held for admin approval.
+    #[serde(rename = "approvalThreshold", default)]
+    pub approval_threshold: Option<Amount>,
+}
+
+impl WithdrawalLimits {
+    /// These limits, with `defaults` for any that are not set.
+    pub fn or(&self, defaults: &WithdrawalLimits) -> WithdrawalLimits {
+        WithdrawalLimits {
+            daily: self.daily.or(defaults.daily),
+            monthly: self.monthly.or(defaults.monthly),
+            approval_threshold: self.approval_threshold.or(defaults.approval_threshold),
+        }
+    }
+}
+
+/// An address a user allowed withdrawals to. Once a user has one, they can
+/// only withdraw to their allowed addresses, and only after `activeAt`.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawalAddress {
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub id: Thing,
+    #[serde(rename = "userId")]
+    #[serde_as(serialize_as = "DisplayFromStr")]
+    pub user_id: Thing,
+    pub address: String,
+    #[serde(rename = "activeAt")]
+    pub active_at: Datetime,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+}
+
+#[derive(Debug, Error)]
+pub enum WithdrawalError {
+    #[error("{0} is not a valid address")]
+    InvalidAddress(String),
+
+    #[error("insufficient balance")]
+    InsufficientBalance,
+
+    #[error("not enough {token} is held on chain {chain_id} to pay this out")]
+    InsufficientCustody { token: String, chain_id: u64 },
+
+    #[error("withdrawal could not be recorded")]
+    NotRecorded,
+
+    #[error("withdrawal would exceed the {period} limit of {limit}")]
+    LimitExceeded { period: &'static str, limit: Amount },
+
+    #[error("{0} is not on your withdrawal allowlist")]
+    AddressNotAllowed(String),
+
+    #[error("{0} can only be withdrawn to after its waiting period")]
+    AddressCoolingOff(String),
+
+    #[error("{0} is already on your withdrawal allowlist")]
+    AddressAlreadyAllowed(String),
+
+    #[error("{0} is not on your withdrawal allowlist")]
+    AddressNotListed(String),
+
+    #[error("the allowlist confirmation must be signed by a linked wallet and state \"{0}\"")]
+    AllowlistNotConfirmed(String),
+
+    #[error("withdrawal fee could not be estimated: {0}")]
+    FeeUnavailable(String),
+
+    #[error("withdrawal fee is {fee}, more than the accepted {max_fee}")]
+    FeeChanged { fee: Amount, max_fee: Amount },
+
+    #[error("withdrawal {0} not found")]
+    NotFound(String),
+
+    #[error("user {0} not found")]
+    UserNotFound(String),
+
+    #[error("withdrawal {0} is not waiting for approval")]
+    NotPendingApproval(String),
+
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+
+    #[error(transparent)]
+    Ledger(#[from] Box<LedgerError>),
+
+    #[error(transparent)]
+    Surrealdb(#[from] Box<surrealdb::Error>),
+}
+
+impl From<surrealdb::Error> for WithdrawalError {
+    fn from(error: surrealdb::Error) -> Self {
+        WithdrawalError::Surrealdb(Box::new(error))
+    }
+}
+
+impl From<LedgerError> for WithdrawalError {
+    fn from(error: LedgerError) -> Self {
+        WithdrawalError::Ledger(Box::new(error))
+    }
+}
+
+impl WithdrawalError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            WithdrawalError::InvalidAddress(_) => StatusCode::BAD_REQUEST,
+            WithdrawalError::Money(error) => error.status_code(),
+            WithdrawalError::Token(error) => error.status_code(),
+            WithdrawalError::Chain(error) => error.status_code(),
+            WithdrawalError::InsufficientBalance
+            | WithdrawalError::InsufficientCustody { .. }
+            | WithdrawalError::LimitExceeded { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            WithdrawalError::AddressNotAllowed(_)
+            | WithdrawalError::AddressCoolingOff(_)
+            | WithdrawalError::AllowlistNotConfirmed(_) => StatusCode::FORBIDDEN,
+            WithdrawalError::AddressAlreadyAllowed(_)
+            | WithdrawalError::NotPendingApproval(_)
+            | WithdrawalError::FeeChanged { .. } => StatusCode::CONFLICT,
+            WithdrawalError::FeeUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            WithdrawalError::NotFound(_)
+            | WithdrawalError::UserNotFound(_)
+            | WithdrawalError::AddressNotListed(_) => StatusCode::NOT_FOUND,
+            WithdrawalError::NotRecorded
+            | WithdrawalError::Ledger(_)
+            | WithdrawalError::Surrealdb(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
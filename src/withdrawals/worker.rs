@@ -0,0 +1,429 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use alloy::eips::eip1559::Eip1559Estimation;
+use alloy::eips::BlockId;
+use alloy::primitives::{Address, Bytes, TxHash};
+use alloy::providers::{DynProvider, Provider};
+use alloy::rpc::types::{TransactionReceipt, TransactionRequest};
+
+use super::models::{Withdrawal, WithdrawalStatus};
+use crate::chain::registry::Chain;
+use crate::chain::{self, Confirmation};
+use crate::hot_wallet::NonceLock;
+use crate::AppState;
+
+/// Moves every queued withdrawal on `chain_id` through its statuses until it
+/// is confirmed or refunded. Runs for the lifetime of the server under
+/// [`crate::worker::supervise`], once per chain.
+pub async fn run(state: AppState, chain_id: u64) -> anyhow::Result<()> {
+    let chain = state.chains.get(chain_id)?;
+    let poll_interval = Duration::from_secs(state.withdrawal_poll_interval_secs);
+
+    chain.hot_wallet.recover(&state.database).await?;
+
+    println!(
+        "Withdrawal worker started for {} on chain {}",
+        chain.hot_wallet.address(),
+        chain_id
+    );
+
+    loop {
+        for withdrawal in super::pending(&state.database, chain_id).await? {
+            if let Err(err) = process(&state, chain, &withdrawal).await {
+                println!("Error processing withdrawal {}: {:?}", withdrawal.id, err);
+            }
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+async fn process(state: &AppState, chain: &Chain, withdrawal: &Withdrawal) -> anyhow::Result<()> {
+    let db = &state.database;
+    let hot_wallet = &chain.hot_wallet;
+
+    match withdrawal.status {
+        WithdrawalStatus::Requested => {
+            // A withdrawal whose transfer cannot even be built never will be,
+            // and nothing was signed for it, so it can be refunded.
+            let tx = match transfer(state, withdrawal) {
+                Ok(tx) => tx,
+                Err(err) => {
+                    println!("Withdrawal {} cannot be sent: {}", withdrawal.id, err);
+                    super::mark_failed(db, withdrawal, &err.to_string()).await?;
+                    return Ok(());
+                }
+            };
+
+            // Held until the transaction is broadcast, so transactions reach
+            // the node in nonce order. If signing fails, say because the node
+            // is unreachable, the withdrawal stays requested and is signed on
+            // the next pass.
+            let mut next_nonce = hot_wallet.lock().await;
+            let (tx_hash, raw_tx) = sign(state, chain, &mut next_nonce, withdrawal, tx).await?;
+            broadcast(state, chain, &mut next_nonce, withdrawal, tx_hash, &raw_tx).await
+        }
+        WithdrawalStatus::Signed | WithdrawalStatus::Broadcast => {
+            let (Some(tx_hash), Some(raw_tx), Some(nonce)) =
+                (&withdrawal.tx_hash, &withdrawal.raw_tx, withdrawal.nonce)
+            else {
+                return Err(anyhow::anyhow!("Withdrawal has no signed transaction"));
+            };
+            let tx_hash = TxHash::from_str(tx_hash)?;
+            let mut tx_hashes = withdrawal
+                .tx_hashes
+                .iter()
+                .map(|tx_hash| TxHash::from_str(tx_hash))
+                .collect::<Result<Vec<_>, _>>()?;
+            if !tx_hashes.contains(&tx_hash) {
+                tx_hashes.push(tx_hash);
+            }
+
+            if track(state, chain, withdrawal, &tx_hashes, nonce).await? {
+                return Ok(());
+            }
+
+            let mut next_nonce = hot_wallet.lock().await;
+            if withdrawal.status == WithdrawalStatus::Broadcast && is_stuck(state, withdrawal) {
+                if let Some((tx_hash, raw_tx)) =
+                    replace(state, chain, &next_nonce, withdrawal, nonce).await?
+                {
+                    return broadcast(state, chain, &mut next_nonce, withdrawal, tx_hash, &raw_tx)
+                        .await;
+                }
+            }
+
+            // Not mined yet. Sending it again is harmless, and puts it back
+            // in the mempool if the node dropped it.
+            let raw_tx = Bytes::from_str(raw_tx)?;
+            broadcast(state, chain, &mut next_nonce, withdrawal, tx_hash, &raw_tx).await
+        }
+        WithdrawalStatus::Failed => {
+            super::refund(db, withdrawal).await?;
+            println!("Withdrawal {} refunded", withdrawal.id);
+            Ok(())
+        }
+        WithdrawalStatus::PendingApproval
+        | WithdrawalStatus::Confirmed
+        | WithdrawalStatus::Refunded => Ok(()),
+    }
+}
+
+/// Signs `tx`, the token transfer for a withdrawal, with the next nonce and
+/// stores it before anything is sent, so the same transaction is rebroadcast
+/// after a restart.
+async fn sign(
+    state: &AppState,
+    chain: &Chain,
+    next_nonce: &mut NonceLock<'_>,
+    withdrawal: &Withdrawal,
+    tx: TransactionRequest,
+) -> anyhow::Result<(TxHash, Bytes)> {
+    let fees = chain.hot_wallet.estimate_fees().await?;
+    let max_fee_per_gas = fees.max_fee_per_gas.min(state.withdrawal_max_fee_per_gas);
+    let fees = Eip1559Estimation {
+        max_fee_per_gas,
+        max_priority_fee_per_gas: fees.max_priority_fee_per_gas.min(max_fee_per_gas),
+    };
+
+    let signed = chain
+        .hot_wallet
+        .sign(next_nonce, tx, **next_nonce, fees)
+        .await?;
+    super::mark_signed(
+        &state.database,
+        withdrawal,
+        chain.hot_wallet.address(),
+        &signed,
+    )
+    .await?;
+    **next_nonce = signed.nonce + 1;
+
+    println!(
+        "Withdrawal {} signed as {} with nonce {}",
+        withdrawal.id, signed.hash, signed.nonce
+    );
+
+    Ok((signed.hash, signed.raw))
+}
+
+/// Signs a replacement for a withdrawal's stuck transaction, with the same
+/// nonce and bumped fees. Returns `None` once the fees are at the cap and
+/// cannot be bumped any further.
+async fn replace(
+    state: &AppState,
+    chain: &Chain,
+    lock: &NonceLock<'_>,
+    withdrawal: &Withdrawal,
+    nonce: u64,
+) -> anyhow::Result<Option<(TxHash, Bytes)>> {
+    let (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) = (
+        withdrawal.max_fee_per_gas,
+        withdrawal.max_priority_fee_per_gas,
+    ) else {
+        return Ok(None);
+    };
+    let previous = Eip1559Estimation {
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+    };
+    let current = chain.hot_wallet.estimate_fees().await?;
+
+    let Some(fees) = bump_fees(
+        previous,
+        current,
+        state.withdrawal_fee_bump_percent,
+        state.withdrawal_max_fee_per_gas,
+    ) else {
+        println!(
+            "Withdrawal {} is stuck at the fee cap of {} wei",
+            withdrawal.id, state.withdrawal_max_fee_per_gas
+        );
+        return Ok(None);
+    };
+
+    let signed = chain
+        .hot_wallet
+        .sign(lock, transfer(state, withdrawal)?, nonce, fees)
+        .await?;
+    super::mark_replaced(&state.database, withdrawal, &signed).await?;
+
+    println!(
+        "Withdrawal {} replaced with {} at {} wei per gas",
+        withdrawal.id, signed.hash, fees.max_fee_per_gas
+    );
+
+    Ok(Some((signed.hash, signed.raw)))
+}
+
+/// Raises both fees by `percent`, or to what the network currently asks if
+/// that is higher, without going over `cap`. Nodes only accept a replacement
+/// that raises both fees by at least `percent`.
+fn bump_fees(
+    previous: Eip1559Estimation,
+    current: Eip1559Estimation,
+    percent: u128,
+    cap: u128,
+) -> Option<Eip1559Estimation> {
+    let bump = |fee: u128| fee.saturating_mul(100 + percent).div_ceil(100);
+    let max_fee_per_gas = bump(previous.max_fee_per_gas)
+        .max(current.max_fee_per_gas)
+        .min(cap);
+    let max_priority_fee_per_gas = bump(previous.max_priority_fee_per_gas)
+        .max(current.max_priority_fee_per_gas)
+        .min(max_fee_per_gas);
+
+    let bumped = max_fee_per_gas >= bump(previous.max_fee_per_gas)
+        && max_priority_fee_per_gas >= bump(previous.max_priority_fee_per_gas);
+
+    bumped.then_some(Eip1559Estimation {
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+    })
+}
+
+/// Whether the current transaction of a withdrawal has waited longer than
+/// it should to be mined.
+fn is_stuck(state: &AppState, withdrawal: &Withdrawal) -> bool {
+    let Some(signed_at) = &withdrawal.signed_at else {
+        return false;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or_default();
+
+    now - signed_at.0.timestamp() >= state.withdrawal_stuck_after_secs as i64
+}
+
+/// The token transfer a withdrawal pays out with.
+fn transfer(state: &AppState, withdrawal: &Withdrawal) -> anyhow::Result<TransactionRequest> {
+    let to = Address::from_str(&withdrawal.address)?;
+    let token = state
+        .tokens
+        .registered_on(withdrawal.chain_id, &withdrawal.token)?
+        .token();
+
+    Ok(super::transfer_request(to, withdrawal.amount, &token)?)
+}
+
+async fn broadcast(
+    state: &AppState,
+    chain: &Chain,
+    next_nonce: &mut NonceLock<'_>,
+    withdrawal: &Withdrawal,
+    tx_hash: TxHash,
+    raw_tx: &Bytes,
+) -> anyhow::Result<()> {
+    let provider = chain.hot_wallet.provider();
+
+    match provider.send_raw_transaction(raw_tx).await {
+        Ok(_) => {}
+        Err(err) if err.to_string().contains("already known") => {}
+        // The nonce was taken by a transaction we did not send. If this one
+        // was never accepted by the node it can safely be signed again with
+        // a fresh nonce; if it was, the tracker fails it once the other
+        // transaction is mined.
+        Err(err) if is_nonce_taken(&err.to_string()) => {
+            println!("Broadcasting withdrawal {} failed: {}", withdrawal.id, err);
+            chain.hot_wallet.resync(next_nonce).await?;
+
+            let mined = provider.get_transaction_receipt(tx_hash).await?.is_some();
+            if withdrawal.status == WithdrawalStatus::Requested && !mined {
+                super::resign(&state.database, withdrawal).await?;
+            }
+            return Ok(());
+        }
+        // Left signed; it is sent again on the next pass until it is mined
+        // or its nonce is taken.
+        Err(err) => {
+            println!("Broadcasting withdrawal {} failed: {}", withdrawal.id, err);
+            return Ok(());
+        }
+    }
+
+    if withdrawal.status != WithdrawalStatus::Broadcast {
+        super::mark_broadcast(&state.database, withdrawal).await?;
+        println!("Withdrawal {} broadcast as {}", withdrawal.id, tx_hash);
+    }
+
+    Ok(())
+}
+
+pub fn is_nonce_taken(error: &str) -> bool {
+    let error = error.to_lowercase();
+    error.contains("nonce too low") || error.contains("replacement transaction underpriced")
+}
+
+/// Follows a sent withdrawal on-chain. Any of its transactions may be the
+/// one that gets mined. Returns `false` while none of them has been and the
+/// withdrawal should be sent again.
+async fn track(
+    state: &AppState,
+    chain: &Chain,
+    withdrawal: &Withdrawal,
+    tx_hashes: &[TxHash],
+    nonce: u64,
+) -> anyhow::Result<bool> {
+    let db = &state.database;
+    // Receipts, the nonce and confirmations all have to come from the same
+    // endpoint to agree with each other.
+    let provider = &chain.rpc.pinned();
+
+    let mut mined = find_mined(provider, tx_hashes).await?;
+
+    if mined.is_none() {
+        if let (Some(_), Some(tx_hash)) = (&withdrawal.block_hash, &withdrawal.tx_hash) {
+            println!("Withdrawal {} was reorged out", withdrawal.id);
+            super::mark_mined(db, withdrawal, tx_hash, None, 0).await?;
+        }
+
+        let latest = provider.get_block_number().await?;
+        let mined_nonces = provider
+            .get_transaction_count(chain.hot_wallet.address())
+            .block_id(BlockId::number(latest))
+            .await?;
+        if mined_nonces <= nonce {
+            if withdrawal.nonce_used_at.is_some() {
+                super::mark_nonce_used(db, withdrawal, None).await?;
+            }
+            return Ok(false);
+        }
+
+        // One of ours may have been mined since its receipt was looked up,
+        // or the endpoint that answered may be behind the one counting the
+        // nonce.
+        mined = find_mined(provider, tx_hashes).await?;
+    }
+
+    let Some((tx_hash, receipt)) = mined else {
+        // The nonce is used by a transaction that is not ours. Only once that
+        // has held for as many blocks as a confirmation takes can none of ours
+        // be mined anymore, and the withdrawal be refunded.
+        let latest = provider.get_block_number().await?;
+        let Some(used_at) = withdrawal.nonce_used_at else {
+            println!(
+                "Nonce of withdrawal {} was used by another transaction",
+                withdrawal.id
+            );
+            super::mark_nonce_used(db, withdrawal, Some(latest)).await?;
+            return Ok(true);
+        };
+        if latest.saturating_sub(used_at) >= chain.config.confirmations {
+            println!("Withdrawal {} was replaced on-chain", withdrawal.id);
+            super::mark_failed(db, withdrawal, "Nonce was used by another transaction").await?;
+        }
+        return Ok(true);
+    };
+
+    if withdrawal.status == WithdrawalStatus::Signed {
+        super::mark_broadcast(db, withdrawal).await?;
+    }
+
+    if !receipt.status() {
+        println!("Withdrawal {} reverted", withdrawal.id);
+        super::mark_failed(db, withdrawal, "Transaction reverted").await?;
+        return Ok(true);
+    }
+
+    let tx_hash = tx_hash.to_string();
+    let block_number = receipt
+        .block_number
+        .ok_or_else(|| anyhow::anyhow!("No block number found"))?;
+    let block_hash = receipt
+        .block_hash
+        .ok_or_else(|| anyhow::anyhow!("No block hash found"))?;
+
+    let required = chain.config.confirmations;
+    match chain::confirmation(provider, block_number, block_hash, required).await? {
+        Confirmation::Confirmed { confirmations } => {
+            super::mark_confirmed(
+                db,
+                withdrawal,
+                &tx_hash,
+                block_number,
+                &block_hash.to_string(),
+                confirmations,
+                (receipt.gas_used, receipt.effective_gas_price),
+            )
+            .await?;
+            println!(
+                "Withdrawal {} confirmed as {}, paying {} gas at {} wei",
+                withdrawal.id, tx_hash, receipt.gas_used, receipt.effective_gas_price
+            );
+        }
+        Confirmation::Pending { confirmations } => {
+            super::mark_mined(
+                db,
+                withdrawal,
+                &tx_hash,
+                Some((block_number, &block_hash.to_string())),
+                confirmations,
+            )
+            .await?;
+        }
+        // The transaction is back in the mempool; keep waiting for it to be
+        // mined again.
+        Confirmation::Reorged => {
+            println!("Withdrawal {} was reorged out", withdrawal.id);
+            super::mark_mined(db, withdrawal, &tx_hash, None, 0).await?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// The first of a withdrawal's transactions that has a receipt.
+async fn find_mined(
+    provider: &DynProvider,
+    tx_hashes: &[TxHash],
+) -> anyhow::Result<Option<(TxHash, TransactionReceipt)>> {
+    for tx_hash in tx_hashes {
+        if let Some(receipt) = provider.get_transaction_receipt(*tx_hash).await? {
+            return Ok(Some((*tx_hash, receipt)));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,28 @@
+//! Long-running background tasks.
+
+use std::future::Future;
+use std::time::Duration;
+
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Runs `task` and starts it again whenever it returns an error or panics,
+/// so a single failure cannot stop a background worker for good.
+pub async fn supervise<F, Fut>(name: String, task: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    loop {
+        match tokio::spawn(task()).await {
+            Ok(Ok(())) => {
+                println!("{} worker stopped", name);
+                return;
+            }
+            Ok(Err(err)) => println!("{} worker failed: {:?}", name, err),
+            Err(err) => println!("{} worker panicked: {}", name, err),
+        }
+
+        println!("Restarting {} worker in {:?}", name, RESTART_DELAY);
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}